@@ -0,0 +1,145 @@
+//! Derive macros for [naive-stm](https://docs.rs/naive-stm).
+//! Use them through the `derive` feature of `naive-stm`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataStruct, DeriveInput, Error,
+    Fields, Ident, ItemFn,
+};
+
+/// Implements `naive_stm::Trackable` for a struct whose fields
+/// are STM variables, e.g. `StmCell` and `StmMap`.
+///
+/// For `struct Account`, it generates `TxAccount<'tx>` returned by
+/// `Tx::track`, which keeps the handles of all the fields tracked
+/// by the transaction. Every field `balance` gets the accessors `balance()` and
+/// `balance_mut()` returning its handle, e.g. `TxCell`.
+/// The view and the accessors have the visibility of the struct and the fields.
+#[proc_macro_derive(Stm)]
+pub fn derive_stm(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Adds the parameter `tx: &naive_stm::Tx` in front of the parameters
+/// of a free function, so the function can track STM variables,
+/// e.g. with `track!`. It's called with the transaction as the first argument.
+///
+/// The parameter is named by the argument of the attribute if it's given,
+/// e.g. `#[transactional(outer)]`.
+#[proc_macro_attribute]
+pub fn transactional(args: TokenStream, input: TokenStream) -> TokenStream {
+    let tx = if args.is_empty() {
+        Ident::new("tx", Span::call_site())
+    } else {
+        parse_macro_input!(args as Ident)
+    };
+    let item = parse_macro_input!(input as ItemFn);
+    transactional_fn(tx, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(DataStruct {
+        fields: Fields::Named(fields),
+        ..
+    }) = &input.data
+    else {
+        return Err(Error::new_spanned(
+            &input,
+            "`Stm` can only be derived for structs with named fields",
+        ));
+    };
+    let fields = &fields.named;
+    let DeriveInput {
+        vis,
+        ident,
+        generics,
+        ..
+    } = &input;
+    let tx_ident = format_ident!("Tx{}", ident);
+    let names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let visibilities = fields.iter().map(|field| &field.vis);
+    let mut_names = names
+        .iter()
+        .map(|name| format_ident!("{}_mut", name.as_ref().unwrap()));
+
+    // Every field must be an STM variable
+    let mut generics = generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in &types {
+        where_clause
+            .predicates
+            .push(parse_quote!(#ty: ::naive_stm::StmVar));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let mut tx_generics = generics.clone();
+    tx_generics.params.insert(0, parse_quote!('tx));
+    let (tx_impl_generics, tx_ty_generics, _) = tx_generics.split_for_impl();
+
+    let doc = format!(
+        "Handles of the fields of [`{ident}`] tracked by a transaction"
+    );
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #tx_ident #tx_impl_generics #where_clause {
+            #(
+                #names: ::naive_stm::TxRef<
+                    'tx,
+                    <#types as ::naive_stm::StmVar>::TxVar,
+                >,
+            )*
+        }
+
+        impl #tx_impl_generics #tx_ident #tx_ty_generics #where_clause {
+            #(
+                #visibilities fn #names(
+                    &self,
+                ) -> &<#types as ::naive_stm::StmVar>::TxVar {
+                    &self.#names
+                }
+
+                #visibilities fn #mut_names(
+                    &mut self,
+                ) -> &mut <#types as ::naive_stm::StmVar>::TxVar {
+                    &mut self.#names
+                }
+            )*
+        }
+
+        impl #impl_generics ::naive_stm::Trackable for #ident #ty_generics
+        #where_clause
+        {
+            type Tracked<'tx> = #tx_ident #tx_ty_generics;
+
+            fn track<'tx, __E>(
+                &self,
+                tx: &'tx ::naive_stm::Tx,
+            ) -> ::naive_stm::Result<Self::Tracked<'tx>, __E> {
+                ::std::result::Result::Ok(#tx_ident {
+                    #(#names: tx.track(&self.#names)?,)*
+                })
+            }
+        }
+    })
+}
+
+fn transactional_fn(tx: Ident, mut item: ItemFn) -> syn::Result<TokenStream2> {
+    if let Some(receiver) = item.sig.receiver() {
+        return Err(Error::new_spanned(
+            receiver,
+            "`#[transactional]` can only be applied to free functions",
+        ));
+    }
+    item.sig
+        .inputs
+        .insert(0, parse_quote!(#tx: &::naive_stm::Tx));
+    Ok(quote!(#item))
+}
@@ -0,0 +1,124 @@
+use naive_stm::{Error, StmCell, StmMap, StmQueue, Tx};
+use naive_stm_derive::Stm;
+use std::thread;
+
+#[derive(Stm)]
+struct Account {
+    balance: StmCell<u64>,
+    history: StmQueue<i64>,
+    pub(crate) limits: StmMap<&'static str, u64>,
+}
+
+impl Account {
+    fn new(balance: u64) -> Self {
+        Self {
+            balance: StmCell::new(balance),
+            history: StmQueue::new(),
+            limits: StmMap::from_iter([("transfer", 60)]),
+        }
+    }
+}
+
+#[derive(Stm)]
+struct Pair<T>
+where
+    T: Clone,
+{
+    left: StmCell<T>,
+    right: StmCell<T>,
+}
+
+fn transfer(
+    tx: &Tx,
+    from: &Account,
+    to: &Account,
+    amount: u64,
+) -> Result<(), Error> {
+    let mut from = tx.track(from)?;
+    let mut to = tx.track(to)?;
+    let limit = from.limits().get("transfer")?.as_deref().copied();
+    if limit.is_some_and(|limit| amount > limit) || **from.balance() < amount {
+        return Tx::abort();
+    }
+    *from.balance_mut().get_mut() -= amount;
+    from.history_mut().push(-(amount as i64));
+    *to.balance_mut().get_mut() += amount;
+    to.history_mut().push(amount as i64);
+    Ok(())
+}
+
+#[test]
+fn track_struct() {
+    let alice = Account::new(100);
+    let bob = Account::new(0);
+
+    Tx::run(|tx| transfer(tx, &alice, &bob, 30)).unwrap();
+    assert!(Tx::run(|tx| transfer(tx, &alice, &bob, 61)).is_err());
+
+    Tx::run(|tx| -> Result<(), Error> {
+        let tx_alice = tx.track(&alice)?;
+        // The fields are tracked as usual
+        assert!(matches!(
+            tx.track::<_, ()>(&alice.balance),
+            Err(Error::TransactionVariableIsInUse(_))
+        ));
+        assert_eq!(**tx_alice.balance(), 70);
+        assert_eq!(tx_alice.history().peek()?.as_deref(), Some(&-30));
+        assert_eq!(**tx.track(&bob.balance)?, 30);
+        assert_eq!(tx.track(&bob.history)?.pop()?, Some(30));
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn concurrent_transfers() {
+    let accounts: Vec<_> = (0..4).map(|_| Account::new(1_000)).collect();
+    thread::scope(|scope| {
+        for i in 0..accounts.len() {
+            let accounts = &accounts;
+            scope.spawn(move || {
+                for _ in 0..100 {
+                    let from = &accounts[i];
+                    let to = &accounts[(i + 1) % accounts.len()];
+                    Tx::run(|tx| transfer(tx, from, to, 7)).unwrap();
+                }
+            });
+        }
+    });
+    let total = Tx::run(|tx| -> Result<u64, Error> {
+        accounts.iter().try_fold(0, |total, account| {
+            Ok(total + **tx.track(account)?.balance())
+        })
+    })
+    .unwrap();
+    assert_eq!(total, 4_000);
+}
+
+#[test]
+fn generic_struct() {
+    let pair = Pair {
+        left: StmCell::new("left".to_owned()),
+        right: StmCell::new("right".to_owned()),
+    };
+    Tx::run(|tx| -> Result<(), Error> {
+        let mut pair = tx.track(&pair)?;
+        let left = pair.left_mut().take();
+        let right = std::mem::replace(pair.right_mut().get_mut(), left);
+        *pair.left_mut().get_mut() = right;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        Tx::run(|tx| -> Result<_, Error> { Ok(tx.track(&pair.left)?.clone()) })
+            .unwrap(),
+        "right"
+    );
+    assert_eq!(
+        Tx::run(|tx| -> Result<_, Error> {
+            Ok(tx.track(&pair.right)?.clone())
+        })
+        .unwrap(),
+        "left"
+    );
+}
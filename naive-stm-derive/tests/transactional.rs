@@ -0,0 +1,48 @@
+use naive_stm::{atomically, track, Result, StmCell, StmQueue, Tx};
+use naive_stm_derive::{transactional, Stm};
+
+#[derive(Stm)]
+struct Order {
+    items: StmQueue<&'static str>,
+    total: StmCell<u64>,
+}
+
+#[transactional]
+fn add_item(order: &Order, item: &'static str, price: u64) -> Result {
+    let mut order = tx.track(order)?;
+    order.items_mut().push(item);
+    **order.total_mut() += price;
+    Ok(())
+}
+
+#[transactional(outer)]
+fn move_cell(from: &StmCell<u64>, to: &StmCell<u64>) -> Result<u64> {
+    let tx = outer;
+    track!(tx, from, to);
+    let value = std::mem::take(from.get_mut());
+    **to += value;
+    Ok(**to)
+}
+
+#[test]
+fn transactional() {
+    let order = Order {
+        items: StmQueue::new(),
+        total: StmCell::new(0),
+    };
+    Tx::run(|tx| {
+        add_item(tx, &order, "book", 20)?;
+        add_item(tx, &order, "pen", 2)
+    })
+    .unwrap();
+    let (items, total) = atomically!(order => {
+        let items: Vec<_> = order.items().iter().collect::<Result<_>>()?;
+        (items.len(), **order.total())
+    })
+    .unwrap();
+    assert_eq!((items, total), (2, 22));
+
+    let (a, b) = (StmCell::new(3), StmCell::new(4));
+    assert_eq!(Tx::run(|tx| move_cell(tx, &a, &b)).unwrap(), 7);
+    assert_eq!(atomically!(a, b => (**a, **b)).unwrap(), (0, 7));
+}
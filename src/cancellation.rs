@@ -0,0 +1,51 @@
+use crate::variable::Waiter;
+use std::sync::{Arc, Weak};
+
+/// Stops the transactions that are run with it, see [`TxOptions`](crate::TxOptions).
+///
+/// Clones of the token share the same state, so one of them can be cancelled
+/// from another thread or task. A transaction that is waiting for a pause
+/// or for a change of its variables is woken up right away.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<parking_lot::Mutex<TokenState>>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: bool,
+    /// Transactions that wait for a pause or a change of variables
+    waiters: Vec<Weak<Waiter>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        let mut state = self.state.lock();
+        state.cancelled = true;
+        for waiter in state.waiters.drain(..) {
+            if let Some(waiter) = waiter.upgrade() {
+                waiter.notify()
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().cancelled
+    }
+
+    /// Registers the waiter to be notified about the cancellation.
+    /// Returns `false` if the token has already been cancelled.
+    pub(crate) fn subscribe(&self, waiter: &Arc<Waiter>) -> bool {
+        let mut state = self.state.lock();
+        if state.cancelled {
+            return false;
+        }
+        state.waiters.retain(|waiter| waiter.strong_count() > 0);
+        state.waiters.push(Arc::downgrade(waiter));
+        true
+    }
+}
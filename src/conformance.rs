@@ -0,0 +1,216 @@
+//! Checks that a custom [`StmVar`] behaves like the built-in containers
+//! under concurrent transactions.
+//!
+//! The checks see the variable as a counter, so they need functions
+//! that create a variable, increment its in-transaction state
+//! and read the counter. The latter two return errors of the variable,
+//! e.g. [`Error::ConcurrentUpdate`](crate::Error::ConcurrentUpdate).
+//! Each check panics if the variable misbehaves,
+//! so they're meant to be called from tests. The module is available
+//! with the `conformance` feature, e.g. for dev-dependencies.
+//!
+//! # Examples
+//!
+//! ```
+//! use naive_stm::{conformance::Conformance, StmQueue};
+//!
+//! Conformance::new(
+//!     StmQueue::new,
+//!     |queue| {
+//!         queue.push(());
+//!         Ok(())
+//!     },
+//!     |queue| queue.iter().try_fold(0, |count, item| item.map(|_| count + 1)),
+//! )
+//! .check_all();
+//! ```
+
+use crate::{Result, StmVar, Tx, TxOptions, TxVar, YieldOnly};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+type NewVar<V> = Box<dyn Fn() -> V + Sync>;
+type Increment<V> = Box<dyn Fn(&mut <V as StmVar>::TxVar) -> Result + Sync>;
+type Read<V> = Box<dyn Fn(&<V as StmVar>::TxVar) -> Result<usize> + Sync>;
+
+pub struct Conformance<V: StmVar> {
+    new_var: NewVar<V>,
+    increment: Increment<V>,
+    read: Read<V>,
+}
+
+impl<V> Conformance<V>
+where
+    V: StmVar + Sync,
+{
+    pub fn new<N, I, R>(new_var: N, increment: I, read: R) -> Self
+    where
+        N: Fn() -> V + Sync + 'static,
+        I: Fn(&mut V::TxVar) -> Result + Sync + 'static,
+        R: Fn(&V::TxVar) -> Result<usize> + Sync + 'static,
+    {
+        Self {
+            new_var: Box::new(new_var),
+            increment: Box::new(increment),
+            read: Box::new(read),
+        }
+    }
+
+    pub fn check_all(&self) {
+        self.concurrent_increments();
+        self.conflicting_commit();
+        self.stale_read();
+        self.isolation();
+        self.rollback();
+        self.retry_wakeup();
+    }
+
+    /// No increment made by concurrent transactions is lost
+    pub fn concurrent_increments(&self) {
+        const THREADS: usize = 4;
+        const INCREMENTS: usize = 100;
+        let var = (self.new_var)();
+        let initial = self.read_committed(&var);
+        let options = TxOptions {
+            attempts: usize::MAX,
+            retry_policy: Box::new(YieldOnly),
+            ..Default::default()
+        };
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    for _ in 0..INCREMENTS {
+                        Tx::run_with_options(&options, |tx| {
+                            (self.increment)(&mut *tx.track(&var)?)?;
+                            Ok(())
+                        })
+                        .unwrap()
+                    }
+                });
+            }
+        });
+        assert_eq!(self.read_committed(&var), initial + THREADS * INCREMENTS);
+    }
+
+    /// A transaction can't be committed if the variable it has read
+    /// has been concurrently changed
+    pub fn conflicting_commit(&self) {
+        let var = (self.new_var)();
+        let initial = self.read_committed(&var);
+        let (result, stats) = Tx::run_with_stats(&Default::default(), |tx| {
+            let mut tx_var = tx.track(&var)?;
+            if tx_var.has_changes() {
+                panic!("The variable has changes right after tracking")
+            }
+            let value = (self.read)(&tx_var)?;
+            if value == initial {
+                self.increment_committed(&var);
+            }
+            (self.increment)(&mut tx_var)?;
+            Ok(())
+        });
+        result.unwrap();
+        assert_eq!(stats.attempts, 2, "The conflict has not been detected");
+        assert_eq!(stats.conflicts, [var.var_id()]);
+        assert_eq!(self.read_committed(&var), initial + 2);
+    }
+
+    /// A transaction can't read the variable if it has been changed
+    /// after the transaction started
+    pub fn stale_read(&self) {
+        let var = (self.new_var)();
+        let mut attempts = 0;
+        let (result, stats) = Tx::run_with_stats(&Default::default(), |tx| {
+            attempts += 1;
+            if attempts == 1 {
+                self.increment_committed(&var);
+            }
+            (self.read)(&*tx.track(&var)?)
+        });
+        let value = result.unwrap();
+        assert_eq!(stats.attempts, 2, "The stale read has not been detected");
+        assert_eq!(value, self.read_committed(&var));
+    }
+
+    /// Changes of a transaction are invisible to other transactions
+    /// until it's committed
+    pub fn isolation(&self) {
+        let var = (self.new_var)();
+        let initial = self.read_committed(&var);
+        Tx::run(|tx| {
+            let mut tx_var = tx.track(&var)?;
+            (self.increment)(&mut tx_var)?;
+            assert!(tx_var.has_changes());
+            assert_eq!((self.read)(&tx_var)?, initial + 1);
+            let concurrent_read = thread::scope(|scope| {
+                scope.spawn(|| self.read_committed(&var)).join().unwrap()
+            });
+            assert_eq!(concurrent_read, initial);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(self.read_committed(&var), initial + 1);
+    }
+
+    /// A rolled back nested transaction restores the in-transaction state
+    /// of the variable
+    pub fn rollback(&self) {
+        let var = (self.new_var)();
+        let initial = self.read_committed(&var);
+        Tx::run(|tx| {
+            (self.increment)(&mut *tx.track(&var)?)?;
+            let result = tx.nested(|tx| {
+                (self.increment)(&mut *tx.track(&var)?)?;
+                Tx::abort()
+            });
+            assert!(result.is_err());
+            assert_eq!((self.read)(&*tx.track(&var)?)?, initial + 1);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(self.read_committed(&var), initial + 1);
+    }
+
+    /// A transaction waiting after [`Tx::retry`] is woken up
+    /// by a change of the variable
+    pub fn retry_wakeup(&self) {
+        let var = (self.new_var)();
+        let initial = self.read_committed(&var);
+        let options = TxOptions {
+            deadline: Some(Instant::now() + Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let result = thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                Tx::run_with_options(&options, |tx| {
+                    let value = (self.read)(&*tx.track(&var)?)?;
+                    if value == initial {
+                        Tx::retry()?;
+                    }
+                    Ok(value)
+                })
+            });
+            thread::sleep(Duration::from_millis(20));
+            self.increment_committed(&var);
+            waiting.join().unwrap()
+        });
+        assert_eq!(
+            result.expect("The waiting transaction has not been woken up"),
+            initial + 1
+        );
+    }
+
+    fn read_committed(&self, var: &V) -> usize {
+        Tx::run(|tx| (self.read)(&*tx.track_shared(var)?)).unwrap()
+    }
+
+    fn increment_committed(&self, var: &V) {
+        Tx::run(|tx| {
+            (self.increment)(&mut *tx.track(var)?)?;
+            Ok(())
+        })
+        .unwrap()
+    }
+}
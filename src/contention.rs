@@ -0,0 +1,246 @@
+use crate::{variable::Waiter, StmVarId};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    thread::{self, ThreadId},
+};
+
+/// Decides whether a transaction waits before its next attempt
+/// for a transaction that contends with it.
+///
+/// Transactions that have failed because of concurrent updates are contenders
+/// until they finish. A transaction asks its manager about the contenders
+/// running in other threads that have failed because of the same variables,
+/// when the transaction commits changes of such variables, and before
+/// the next attempt after its own failure because of them. If the transaction
+/// must wait, its commit fails, and it doesn't start the next attempt
+/// until the contender finishes, so the contender can commit without
+/// being outpaced. A contender doesn't make others wait while it waits
+/// after [`Tx::retry`](crate::Tx::retry).
+///
+/// The decisions must not be cyclic, i.e. if `A` waits for `B`,
+/// `B` must not wait for `A`, and all transactions should use the same manager.
+pub trait ContentionManager: Send + Sync {
+    fn should_wait(
+        &self,
+        context: &ContentionContext,
+        contender: &ContentionContext,
+    ) -> bool;
+
+    /// Whether the manager never makes transactions wait. If so, transactions
+    /// neither look for contenders nor register as contenders themselves.
+    fn never_waits(&self) -> bool {
+        false
+    }
+}
+
+/// Information about a transaction in contention
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ContentionContext {
+    /// See [`TxOptions::priority`](crate::TxOptions::priority)
+    pub priority: u32,
+    /// Number of the attempts that have failed because of concurrent updates
+    pub failed_attempts: usize,
+    /// Unique number, which is smaller for transactions that have
+    /// encountered contention earlier
+    pub ticket: u64,
+}
+
+impl ContentionContext {
+    /// Transactions that have encountered contention earlier take precedence
+    fn is_older_than(&self, other: &Self) -> bool {
+        self.ticket < other.ticket
+    }
+}
+
+/// Never waits for other transactions, so the transaction that commits first wins
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Passive;
+
+impl ContentionManager for Passive {
+    fn should_wait(
+        &self,
+        _: &ContentionContext,
+        _: &ContentionContext,
+    ) -> bool {
+        false
+    }
+
+    fn never_waits(&self) -> bool {
+        true
+    }
+}
+
+/// Waits for contenders with a higher priority,
+/// or for older contenders with the same priority
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Greedy;
+
+impl ContentionManager for Greedy {
+    fn should_wait(
+        &self,
+        context: &ContentionContext,
+        contender: &ContentionContext,
+    ) -> bool {
+        contender.priority > context.priority
+            || contender.priority == context.priority
+                && contender.is_older_than(context)
+    }
+}
+
+/// Waits for contenders that have accumulated more "karma", which is
+/// the priority plus the number of failed attempts, so transactions
+/// that have wasted more work take precedence. Ties are resolved by age.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Karma;
+
+impl Karma {
+    fn karma(context: &ContentionContext) -> u64 {
+        u64::from(context.priority)
+            .saturating_add(context.failed_attempts as u64)
+    }
+}
+
+impl ContentionManager for Karma {
+    fn should_wait(
+        &self,
+        context: &ContentionContext,
+        contender: &ContentionContext,
+    ) -> bool {
+        let (karma, contender_karma) =
+            (Self::karma(context), Self::karma(contender));
+        contender_karma > karma
+            || contender_karma == karma && contender.is_older_than(context)
+    }
+}
+
+pub fn next_ticket() -> u64 {
+    static NEXT_TICKET: AtomicU64 = AtomicU64::new(0);
+    NEXT_TICKET.fetch_add(1, Ordering::Relaxed)
+}
+
+struct Contender {
+    context: ContentionContext,
+    thread_id: ThreadId,
+    /// Variables that have made the attempts of the contender fail
+    conflicts: BTreeSet<StmVarId>,
+    /// Transactions that wait for the contender to finish
+    waiters: Vec<Weak<Waiter>>,
+}
+
+/// Running contenders by ticket
+static CONTENDERS: Mutex<BTreeMap<u64, Contender>> =
+    parking_lot::const_mutex(BTreeMap::new());
+
+/// Lets transactions skip the lock of the contenders
+/// if there is no contention
+static CONTENDERS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a waiter to be notified when the contender that the transaction
+/// must wait for finishes. Only the contenders that have failed because of
+/// any of the `conflicts` of the transaction are considered.
+pub fn contender_to_wait_for(
+    manager: &dyn ContentionManager,
+    conflicts: &[StmVarId],
+    context: &ContentionContext,
+) -> Option<Arc<Waiter>> {
+    if conflicts.is_empty() || CONTENDERS_COUNT.load(Ordering::SeqCst) == 0 {
+        return None;
+    }
+    let mut contenders = CONTENDERS.lock();
+    let (contender, _) =
+        find_contender(&mut contenders, manager, conflicts, context)?;
+    let waiter = Waiter::new();
+    contender.waiters.push(Arc::downgrade(&waiter));
+    Some(waiter)
+}
+
+/// Returns a variable changed by the transaction that has made a contender
+/// fail if the transaction must wait for the contender. The commit would make
+/// the contender fail again, so the transaction must fail instead.
+pub fn conflict_with_contender(
+    manager: &dyn ContentionManager,
+    changed_vars: &[StmVarId],
+    context: impl FnOnce() -> ContentionContext,
+) -> Option<StmVarId> {
+    if changed_vars.is_empty() || CONTENDERS_COUNT.load(Ordering::SeqCst) == 0 {
+        return None;
+    }
+    let context = context();
+    let mut contenders = CONTENDERS.lock();
+    find_contender(&mut contenders, manager, changed_vars, &context)
+        .map(|(_, var_id)| var_id)
+}
+
+/// Finds a contender that the transaction must wait for among the ones
+/// that have failed because of the variables, and returns it along with
+/// the first such variable
+fn find_contender<'a>(
+    contenders: &'a mut BTreeMap<u64, Contender>,
+    manager: &dyn ContentionManager,
+    vars: &[StmVarId],
+    context: &ContentionContext,
+) -> Option<(&'a mut Contender, StmVarId)> {
+    let thread_id = thread::current().id();
+    contenders.values_mut().find_map(|contender| {
+        // Transactions run within an attempt of a contender must not wait for it
+        if contender.thread_id == thread_id
+            || !manager.should_wait(context, &contender.context)
+        {
+            return None;
+        }
+        let var_id = vars
+            .iter()
+            .find(|var_id| contender.conflicts.contains(var_id))?;
+        Some((contender, *var_id))
+    })
+}
+
+/// Registers the transaction as a contender until the guard is dropped
+pub fn register(
+    context: ContentionContext,
+    conflicts: &[StmVarId],
+) -> ContenderGuard {
+    let ticket = context.ticket;
+    let contender = Contender {
+        context,
+        thread_id: thread::current().id(),
+        conflicts: conflicts.iter().copied().collect(),
+        waiters: Vec::new(),
+    };
+    CONTENDERS.lock().insert(ticket, contender);
+    CONTENDERS_COUNT.fetch_add(1, Ordering::SeqCst);
+    ContenderGuard { ticket }
+}
+
+/// Unregisters the contender and notifies the transactions waiting for it
+pub struct ContenderGuard {
+    ticket: u64,
+}
+
+impl ContenderGuard {
+    pub fn update(&self, context: ContentionContext, conflicts: &[StmVarId]) {
+        if let Some(contender) = CONTENDERS.lock().get_mut(&self.ticket) {
+            contender.context = context;
+            contender.conflicts.extend(conflicts);
+        }
+    }
+}
+
+impl Drop for ContenderGuard {
+    fn drop(&mut self) {
+        let contender = CONTENDERS.lock().remove(&self.ticket);
+        CONTENDERS_COUNT.fetch_sub(1, Ordering::SeqCst);
+        let waiters = contender.map(|contender| contender.waiters);
+        for waiter in waiters.into_iter().flatten() {
+            if let Some(waiter) = waiter.upgrade() {
+                waiter.notify()
+            }
+        }
+    }
+}
@@ -1,16 +1,40 @@
 //! Software transactional memory
 
+mod cancellation;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod contention;
+mod observer;
+mod retry_policy;
+mod timer;
 mod transaction;
 mod variable;
 
-use std::fmt;
-use variable::StmVarId;
+use std::{
+    cmp::Reverse, collections::BTreeMap, convert::Infallible, fmt, sync::Arc,
+};
 
-pub use transaction::{Tx, TxOptions};
+pub use cancellation::CancellationToken;
+pub use contention::{
+    ContentionContext, ContentionManager, Greedy, Karma, Passive,
+};
+#[cfg(feature = "derive")]
+pub use naive_stm_derive::{transactional, Stm};
+#[cfg(feature = "tracing")]
+pub use observer::TracingObserver;
+pub use observer::{TxEvent, TxObserver};
+pub use retry_policy::{
+    Backoff, Capped, ConstantPause, DecorrelatedJitter, ExponentialBackoff,
+    RetryContext, RetryPolicy, YieldOnly,
+};
+pub use transaction::{
+    LockedTxVar, Trackable, Tx, TxOptions, TxRef, TxSharedRef, TxStats, TxVar,
+};
 pub use variable::{
     cell::{StmCell, TxCell},
     map::{StmMap, TxMap},
     queue::{StmQueue, TxQueue},
+    StmVar, StmVarId, Version, VersionedValue, Waiter,
 };
 
 pub type Result<T = (), E = ()> = std::result::Result<T, Error<E>>;
@@ -18,8 +42,16 @@ pub type Result<T = (), E = ()> = std::result::Result<T, Error<E>>;
 #[derive(Debug)]
 pub enum Error<E = ()> {
     TransactionVariableIsInUse(StmVarId),
-    ConcurrentUpdate,
-    TooManyTransactionRetryAttempts { attempts: usize },
+    ConcurrentUpdate(StmVarId),
+    TransactionRetry,
+    TooManyTransactionRetryAttempts {
+        attempts: usize,
+        /// Variables that have made the attempts fail,
+        /// the most conflicting ones first
+        conflicts: Vec<Conflict>,
+    },
+    DeadlineExceeded,
+    Cancelled,
     TransactionAbort(E),
 }
 
@@ -28,22 +60,164 @@ impl<E> fmt::Display for Error<E> {
         match self {
             Self::TransactionVariableIsInUse(var_id) => write!(
                 f,
-                "Transaction is already tracking the STM variable `{var_id:?}`. \
+                "Transaction is already tracking the STM variable `{var_id}`. \
                 The previous `TxRef` handle for this variable must be dropped \
                 before calling `Tx::track` on it again."
             ),
-            Self::ConcurrentUpdate => write!(
+            Self::ConcurrentUpdate(var_id) => write!(
                 f,
-                "Another transaction concurrently updated an STM variable. \
+                "Another transaction concurrently updated the STM variable `{var_id}`. \
                 Therefore, the current transaction should be retried. \
                 It's a bug if this error escapes the transaction runner."
             ),
-            Self::TooManyTransactionRetryAttempts { attempts } => {
-                write!(f, "The maximum number ({attempts}) of attempts for the transaction has been reached")
+            Self::TransactionRetry => write!(
+                f,
+                "Transaction requested to be retried after a change of the tracked variables. \
+                It's a bug if this error escapes the transaction runner."
+            ),
+            Self::TooManyTransactionRetryAttempts {
+                attempts,
+                conflicts,
+            } => {
+                write!(f, "The maximum number ({attempts}) of attempts for the transaction has been reached")?;
+                for (i, conflict) in conflicts.iter().enumerate() {
+                    let separator = if i == 0 { ". Conflicts:" } else { "," };
+                    write!(f, "{separator} {conflict}")?;
+                }
+                Ok(())
             }
+            Self::DeadlineExceeded => {
+                write!(f, "The deadline for the transaction has been exceeded")
+            }
+            Self::Cancelled => write!(f, "The transaction has been cancelled"),
             Self::TransactionAbort(_) => {
                 write!(f, "Transaction was explicitly aborted")
             }
         }
     }
 }
+
+impl<E: fmt::Debug> std::error::Error for Error<E> {}
+
+/// A variable that has been concurrently updated by other transactions
+/// while a transaction was attempted, i.e. it has raised
+/// [`Error::ConcurrentUpdate`] or it has failed the validation at commit
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Conflict {
+    pub var_id: StmVarId,
+    /// The label of the variable, which is kept
+    /// even if the variable is dropped afterwards
+    pub label: Option<Arc<str>>,
+    /// Number of the attempts that have failed because of the variable
+    pub count: usize,
+}
+
+impl Conflict {
+    /// Counts the conflicts per variable, the most frequent ones first
+    fn from_var_ids(var_ids: &[StmVarId]) -> Vec<Self> {
+        let mut counts = BTreeMap::new();
+        for var_id in var_ids {
+            *counts.entry(*var_id).or_default() += 1;
+        }
+        let mut conflicts: Vec<_> = counts
+            .into_iter()
+            .map(|(var_id, count)| Self {
+                var_id,
+                label: var_id.label(),
+                count,
+            })
+            .collect();
+        conflicts.sort_by_key(|conflict| Reverse(conflict.count));
+        conflicts
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "`{label}`")?,
+            None => write!(f, "`{}`", self.var_id)?,
+        }
+        match self.count {
+            1 => write!(f, " (1 time)"),
+            count => write!(f, " ({count} times)"),
+        }
+    }
+}
+
+impl<E> Error<E> {
+    /// Maps the error of an aborted transaction, leaving other errors untouched
+    pub fn map_abort<F>(self, f: impl FnOnce(E) -> F) -> Error<F> {
+        match self {
+            Self::TransactionVariableIsInUse(var_id) => {
+                Error::TransactionVariableIsInUse(var_id)
+            }
+            Self::ConcurrentUpdate(var_id) => Error::ConcurrentUpdate(var_id),
+            Self::TransactionRetry => Error::TransactionRetry,
+            Self::TooManyTransactionRetryAttempts {
+                attempts,
+                conflicts,
+            } => Error::TooManyTransactionRetryAttempts {
+                attempts,
+                conflicts,
+            },
+            Self::DeadlineExceeded => Error::DeadlineExceeded,
+            Self::Cancelled => Error::Cancelled,
+            Self::TransactionAbort(error) => Error::TransactionAbort(f(error)),
+        }
+    }
+}
+
+impl Error<Infallible> {
+    /// Converts an error of the runtime, which can't be an abort,
+    /// into an error of a transaction that aborts with errors of type `E`,
+    /// see [`LiftError`].
+    pub fn lift<E>(self) -> Error<E> {
+        self.map_abort(|never| match never {})
+    }
+}
+
+/// Propagates errors of the runtime, which are `Error<Infallible>`,
+/// from transactions that abort with custom errors.
+///
+/// The operations of the runtime, e.g. [`Tx::track`] or [`TxQueue::pop`],
+/// return errors of any type, so `?` works for them directly.
+/// The trait is for the results that have a fixed type, e.g. returned by
+/// helper functions that don't abort.
+/// Transactions abort with custom errors by [`Tx::abort_with`].
+///
+/// `Error<E>` can't implement `From<Error<Infallible>>`, since it would
+/// conflict with `From<T> for T`, so `?` needs the explicit conversion.
+///
+/// # Examples
+///
+/// ```
+/// use naive_stm::{Error, LiftError, Result, StmQueue, Tx};
+/// use std::convert::Infallible;
+///
+/// #[derive(Debug, PartialEq)]
+/// struct QueueIsEmpty;
+///
+/// fn is_empty(tx: &Tx, queue: &StmQueue<u32>) -> Result<bool, Infallible> {
+///     tx.track_shared(queue)?.is_empty()
+/// }
+///
+/// let queue = StmQueue::<u32>::new();
+/// let result = Tx::run(|tx| {
+///     if is_empty(tx, &queue).lift()? {
+///         Tx::abort_with(QueueIsEmpty)?;
+///     }
+///     Ok(tx.track(&queue)?.pop()?)
+/// });
+/// assert!(matches!(result, Err(Error::TransactionAbort(QueueIsEmpty))));
+/// ```
+pub trait LiftError<T> {
+    fn lift<E>(self) -> Result<T, E>;
+}
+
+impl<T> LiftError<T> for Result<T, Infallible> {
+    fn lift<E>(self) -> Result<T, E> {
+        self.map_err(Error::lift)
+    }
+}
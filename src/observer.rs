@@ -0,0 +1,95 @@
+use crate::{StmVarId, Version};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Receives lifecycle events of all transactions in the process,
+/// see [`Tx::set_observer`](crate::Tx::set_observer).
+///
+/// Events are reported synchronously by the thread that runs a transaction,
+/// so the observer must be cheap, and it must not run transactions itself.
+pub trait TxObserver: Send + Sync {
+    fn on_event(&self, event: &TxEvent);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TxEvent {
+    /// The transaction function is about to be run. The attempts are counted
+    /// from 1, including the runs after [`Tx::retry`](crate::Tx::retry).
+    AttemptStarted {
+        attempt: usize,
+    },
+    /// The attempt has started to track the variable
+    VarTracked {
+        var_id: StmVarId,
+    },
+    /// The attempt has read a variable that has been concurrently updated
+    ConflictDetected {
+        var_id: StmVarId,
+    },
+    Committed {
+        version: Version,
+    },
+    /// The variable has been concurrently updated,
+    /// so the attempt couldn't be committed
+    CommitFailed {
+        var_id: StmVarId,
+    },
+    /// The run of the transaction has finally failed, e.g. it has been aborted
+    /// or it has run out of attempts
+    Aborted,
+}
+
+static OBSERVER: parking_lot::RwLock<Option<Arc<dyn TxObserver>>> =
+    parking_lot::const_rwlock(None);
+
+/// Lets transactions skip the lock when no observer is installed
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set(observer: Option<Arc<dyn TxObserver>>) {
+    let mut current = OBSERVER.write();
+    INSTALLED.store(observer.is_some(), Ordering::Release);
+    *current = observer;
+}
+
+pub fn emit(event: TxEvent) {
+    if !INSTALLED.load(Ordering::Acquire) {
+        return;
+    }
+    // The lock is released before calling the observer
+    let observer = OBSERVER.read().clone();
+    if let Some(observer) = observer {
+        observer.on_event(&event)
+    }
+}
+
+/// Reports transaction events to the [`tracing`] crate
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl TxObserver for TracingObserver {
+    fn on_event(&self, event: &TxEvent) {
+        match event {
+            TxEvent::AttemptStarted { attempt } => {
+                tracing::trace!(attempt, "STM transaction attempt started")
+            }
+            TxEvent::VarTracked { var_id } => {
+                tracing::trace!(%var_id, "STM variable tracked")
+            }
+            TxEvent::ConflictDetected { var_id } => {
+                tracing::debug!(%var_id, "STM conflict detected")
+            }
+            TxEvent::Committed { version } => {
+                tracing::trace!(?version, "STM transaction committed")
+            }
+            TxEvent::CommitFailed { var_id } => {
+                tracing::debug!(%var_id, "STM transaction commit failed")
+            }
+            TxEvent::Aborted => tracing::debug!("STM transaction aborted"),
+        }
+    }
+}
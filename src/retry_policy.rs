@@ -0,0 +1,124 @@
+use crate::StmVarId;
+use rand::prelude::*;
+use std::time::Duration;
+
+/// Decides what a transaction runner does before the next attempt
+/// of a transaction that has failed because of a concurrent update
+pub trait RetryPolicy: Send + Sync {
+    fn backoff(&self, context: &RetryContext) -> Backoff;
+}
+
+/// Information about a failed attempt of a transaction
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RetryContext {
+    /// Number of the failed attempt, starting from 1
+    pub attempt: usize,
+    /// The pause before the failed attempt, zero for the first attempt
+    pub previous_pause: Duration,
+    /// The variable that has been concurrently updated
+    pub conflicting_var: StmVarId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backoff {
+    /// Sleep before the next attempt. An async runner suspends the task instead.
+    Pause(Duration),
+    /// Yield the thread or the task to the scheduler, and then run
+    /// the next attempt
+    Yield,
+}
+
+/// The same pause before each attempt
+#[derive(Clone, Debug, Default)]
+pub struct ConstantPause {
+    pub pause: Duration,
+    /// If `true`, the pause will be a random value within the range `0 .. pause`
+    pub jitter: bool,
+}
+
+impl RetryPolicy for ConstantPause {
+    fn backoff(&self, _: &RetryContext) -> Backoff {
+        let mut pause = self.pause;
+        if self.jitter {
+            pause = pause.mul_f64(thread_rng().gen())
+        }
+        Backoff::Pause(pause)
+    }
+}
+
+/// The pause is doubled after each failed attempt
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    /// The pause after the first failed attempt
+    pub initial_pause: Duration,
+    /// If `true`, the pause will be a random value within the range
+    /// `0 .. exponential pause`
+    pub jitter: bool,
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn backoff(&self, context: &RetryContext) -> Backoff {
+        let mut pause = self.initial_pause;
+        let mut exponent = context.attempt - 1;
+        // The pause saturates at `Duration::MAX`, which is reached
+        // within a few steps unless the pause is zero
+        while exponent > 0 && !pause.is_zero() && pause != Duration::MAX {
+            let step = exponent.min(31);
+            pause = pause.saturating_mul(1 << step);
+            exponent -= step;
+        }
+        if self.jitter {
+            pause = pause.mul_f64(thread_rng().gen())
+        }
+        Backoff::Pause(pause)
+    }
+}
+
+/// "Decorrelated jitter" backoff: each pause is a random value between
+/// `base_pause` and three times the previous pause, capped at `max_pause`
+#[derive(Clone, Debug)]
+pub struct DecorrelatedJitter {
+    pub base_pause: Duration,
+    pub max_pause: Duration,
+}
+
+impl RetryPolicy for DecorrelatedJitter {
+    fn backoff(&self, context: &RetryContext) -> Backoff {
+        let upper_bound = context
+            .previous_pause
+            .saturating_mul(3)
+            .max(self.base_pause);
+        let pause = thread_rng()
+            .gen_range(self.base_pause..=upper_bound)
+            .min(self.max_pause);
+        Backoff::Pause(pause)
+    }
+}
+
+/// Limits pauses of another policy, e.g. of [`ExponentialBackoff`]
+#[derive(Clone, Debug)]
+pub struct Capped<P> {
+    pub policy: P,
+    pub max_pause: Duration,
+}
+
+impl<P: RetryPolicy> RetryPolicy for Capped<P> {
+    fn backoff(&self, context: &RetryContext) -> Backoff {
+        match self.policy.backoff(context) {
+            Backoff::Pause(pause) => Backoff::Pause(pause.min(self.max_pause)),
+            Backoff::Yield => Backoff::Yield,
+        }
+    }
+}
+
+/// Only yields between attempts, which suits short transactions
+/// under low contention
+#[derive(Clone, Copy, Debug, Default)]
+pub struct YieldOnly;
+
+impl RetryPolicy for YieldOnly {
+    fn backoff(&self, _: &RetryContext) -> Backoff {
+        Backoff::Yield
+    }
+}
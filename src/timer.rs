@@ -0,0 +1,165 @@
+//! A timer for async transactions that doesn't depend on any particular executor
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::Instant,
+};
+
+/// Wakers of pending [`Sleep`] futures, served by a dedicated thread
+#[derive(Default)]
+struct Timers {
+    /// Wakers by the deadline and the id of the timer,
+    /// the earliest deadline first
+    queue: parking_lot::Mutex<BTreeMap<TimerKey, Waker>>,
+    changed: parking_lot::Condvar,
+}
+
+type TimerKey = (Instant, u64);
+
+fn timers() -> &'static Timers {
+    static TIMERS: OnceLock<Timers> = OnceLock::new();
+    TIMERS.get_or_init(|| {
+        thread::Builder::new()
+            .name("naive-stm-timer".to_owned())
+            .spawn(run_timers)
+            .expect("Failed to spawn the timer thread");
+        Timers::default()
+    })
+}
+
+/// Completes at the deadline without blocking an executor thread
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, id: None }
+}
+
+pub struct Sleep {
+    deadline: Instant,
+    /// Id of the timer registered by the first poll
+    id: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = *self
+            .id
+            .get_or_insert_with(|| NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let timers = timers();
+        let mut queue = timers.queue.lock();
+        // Later polls only update the waker of the timer
+        match queue.get_mut(&(self.deadline, id)) {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => {
+                queue.insert((self.deadline, id), cx.waker().clone());
+                timers.changed.notify_one();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            timers().queue.lock().remove(&(self.deadline, id));
+        }
+    }
+}
+
+fn run_timers() {
+    let Timers { queue, changed } = timers();
+    let mut timers = queue.lock();
+    let mut expired = Vec::new();
+    loop {
+        let now = Instant::now();
+        while timers
+            .first_key_value()
+            .is_some_and(|((deadline, _), _)| *deadline <= now)
+        {
+            expired.extend(timers.pop_first().map(|(_, waker)| waker));
+        }
+        if !expired.is_empty() {
+            // A waker may poll the future in place, which locks the timers
+            parking_lot::MutexGuard::unlocked(&mut timers, || {
+                expired.drain(..).for_each(Waker::wake)
+            });
+            continue;
+        }
+        match timers.first_key_value() {
+            Some(((deadline, _), _)) => {
+                let deadline = *deadline;
+                changed.wait_until(&mut timers, deadline);
+            }
+            None => changed.wait(&mut timers),
+        }
+    }
+}
+
+/// Yields the task to the executor once
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{sync::Arc, task::Wake, time::Duration};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Number of the timers registered by the future
+    fn timers_of(sleep: &Sleep) -> usize {
+        let queue = timers().queue.lock();
+        queue.keys().filter(|(_, id)| Some(*id) == sleep.id).count()
+    }
+
+    #[test]
+    fn sleep_registers_one_timer() {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut sleep = sleep_until(Instant::now() + Duration::from_secs(3600));
+        for _ in 0..10 {
+            assert!(Pin::new(&mut sleep).poll(&mut cx).is_pending());
+        }
+        assert_eq!(timers_of(&sleep), 1);
+
+        // The timer is removed when the future is dropped early
+        let key = (sleep.deadline, sleep.id.unwrap());
+        drop(sleep);
+        assert!(!timers().queue.lock().contains_key(&key));
+    }
+}
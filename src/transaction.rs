@@ -1,55 +1,190 @@
-use crate::{variable::StmVar, Error, Result, StmVarId};
-use rand::prelude::*;
+use crate::{
+    cancellation::CancellationToken,
+    contention::{
+        self, ContenderGuard, ContentionContext, ContentionManager, Passive,
+    },
+    observer::{self, TxEvent, TxObserver},
+    retry_policy::{Backoff, ConstantPause, RetryContext, RetryPolicy},
+    timer,
+    variable::{StmVar, Version, Waiter},
+    Conflict, Error, Result, StmCell, StmVarId, TxCell,
+};
 use std::{
     any::Any,
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::{btree_map::Entry, BTreeMap},
+    convert::Infallible,
     fmt,
     ops::{Deref, DerefMut},
+    rc::Rc,
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 /// Options to run a transaction with
 pub struct TxOptions {
     /// How many times a transaction will be retried in case of concurrent updates
     pub attempts: usize,
-    /// A pause before the next attempt to complete a transaction
-    pub retry_pause: Duration,
-    /// If `true`, the pause between transaction attempts will be random
-    /// value withing the range `0 .. retry_pause`
-    pub pause_jitter: bool,
+    /// Decides what to do before the next attempt to complete a transaction
+    pub retry_policy: Box<dyn RetryPolicy>,
+    /// If the deadline has passed, the transaction is not attempted anymore,
+    /// and the runner returns [`Error::DeadlineExceeded`]
+    pub deadline: Option<Instant>,
+    /// If the token is cancelled, the transaction is not attempted anymore,
+    /// and the runner returns [`Error::Cancelled`]
+    pub cancellation: Option<CancellationToken>,
+    /// After this number of attempts has failed because of concurrent updates,
+    /// the next attempts are run pessimistically, i.e. no other transaction
+    /// can commit while such attempt is running, so it's guaranteed to succeed.
+    /// It prevents starvation of long transactions under high contention.
+    ///
+    /// The number must be less than `attempts` to take effect.
+    /// An async runner blocks the executor thread while a pessimistic attempt
+    /// waits for other transactions to commit.
+    ///
+    /// Transactions run within a pessimistic attempt in the same thread
+    /// commit without waiting for it. If they change the variables
+    /// tracked by the attempt, it fails and is retried.
+    pub pessimistic_after: Option<usize>,
+    /// Priority of the transaction for the contention manager
+    pub priority: u32,
+    /// Decides whether the transaction waits for other transactions
+    /// that contend with it, e.g. for higher-priority ones
+    pub contention_manager: Box<dyn ContentionManager>,
 }
 
 impl Default for TxOptions {
     fn default() -> Self {
         Self {
             attempts: 10,
-            retry_pause: Duration::ZERO,
-            pause_jitter: false,
+            retry_policy: Box::new(ConstantPause::default()),
+            deadline: None,
+            cancellation: None,
+            pessimistic_after: None,
+            priority: 0,
+            contention_manager: Box::new(Passive),
         }
     }
 }
 
+/// Statistics of a run of a transaction
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct TxStats {
+    /// How many times the transaction function has been run,
+    /// including the runs after [`Tx::retry`]
+    pub attempts: usize,
+    /// Total time of the pauses between attempts
+    pub pause_time: Duration,
+    /// Number of variables tracked by the last attempt
+    pub tracked_vars: usize,
+    /// Variables that have been concurrently updated, one per failed attempt
+    pub conflicts: Vec<StmVarId>,
+    /// How many attempts have been run pessimistically,
+    /// see [`TxOptions::pessimistic_after`]
+    pub pessimistic_attempts: usize,
+}
+
 enum TrackedVar {
     /// [`TxVar`] is moved into [`TxRef`]
     InUse,
+    /// [`TxVar`] is shared by alive [`TxSharedRef`] handles
+    Shared(Rc<dyn Any>),
     /// [`TxRef`] has been dropped, and it flushed [`TxVar`] back to a transaction
     Pending(Box<dyn TxVar>),
 }
 
+/// State of a transaction that can be restored
+/// if a part of the transaction is rolled back
+struct Checkpoint {
+    /// In-transaction states of variables
+    vars: BTreeMap<StmVarId, Box<dyn TxVar>>,
+    /// Number of hooks of each kind registered before the checkpoint
+    commit_hooks: usize,
+    abort_hooks: usize,
+}
+
+type Hook = Box<dyn FnOnce()>;
+
+/// Abort hooks can outlive the attempt while an async run waits
+type AbortHook = Box<dyn FnOnce() + Send>;
+
+/// Side effects registered by an attempt of a transaction
+#[derive(Default)]
+struct Hooks {
+    on_commit: Vec<Hook>,
+    on_abort: Vec<AbortHook>,
+}
+
+/// Commits of transactions hold the token shared,
+/// while an irrevocable or a pessimistic attempt holds it exclusively
+static COMMIT_TOKEN: parking_lot::RwLock<()> = parking_lot::const_rwlock(());
+
+thread_local! {
+    /// Whether an attempt run by the thread holds the commit token.
+    /// Transactions run within the attempt don't take the token,
+    /// which would deadlock.
+    static HOLDS_COMMIT_TOKEN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The commit token held exclusively by an attempt
+struct CommitToken {
+    _guard: parking_lot::RwLockWriteGuard<'static, ()>,
+}
+
+impl CommitToken {
+    /// Returns `None` if the thread already holds the token
+    fn acquire() -> Option<Self> {
+        if Self::is_held() {
+            return None;
+        }
+        let guard = COMMIT_TOKEN.write();
+        HOLDS_COMMIT_TOKEN.with(|held| held.set(true));
+        Some(Self { _guard: guard })
+    }
+
+    fn is_held() -> bool {
+        HOLDS_COMMIT_TOKEN.with(Cell::get)
+    }
+}
+
+impl Drop for CommitToken {
+    fn drop(&mut self) {
+        HOLDS_COMMIT_TOKEN.with(|held| held.set(false))
+    }
+}
+
 /// Transaction executor
 pub struct Tx {
+    /// Values of STM variables visible to the transaction must not be newer than this version
+    read_version: Version,
     vars: RefCell<BTreeMap<StmVarId, TrackedVar>>,
+    checkpoints: RefCell<Vec<Checkpoint>>,
+    hooks: RefCell<Hooks>,
+    /// Held by an irrevocable or a pessimistic attempt,
+    /// so no other transaction can commit
+    commit_token: Option<CommitToken>,
+    irrevocable: bool,
 }
 
 enum CommitStatus {
-    Success,
-    Fail,
+    Success(Version),
+    /// The variable has been concurrently updated
+    Fail(StmVarId),
 }
 
 impl Tx {
-    /// Run a new transaction which will be automatically retried in case of concurrent updates
+    /// Run a new transaction which will be automatically retried in case of concurrent updates.
+    ///
+    /// If the transaction calls [`retry`](#method.retry), the current thread is parked
+    /// until another transaction changes one of the variables tracked by the attempt,
+    /// and then the transaction is run again. Such retries don't count as attempts.
+    ///
+    /// The operations of the transaction, e.g. [`track`](#method.track), return errors
+    /// of any type, so `?` propagates them from transactions that abort with custom errors.
+    /// If the transaction never aborts, the type of its errors has to be specified,
+    /// e.g. `|tx| -> Result<_> { ... }`.
     pub fn run<F, T, E>(f: F) -> Result<T, E>
     where
         F: FnMut(&Tx) -> Result<T, E>,
@@ -58,44 +193,176 @@ impl Tx {
     }
 
     /// Like [`run`](#method.run) but with non-default options
-    pub fn run_with_options<F, T, E>(
+    pub fn run_with_options<F, T, E>(options: &TxOptions, f: F) -> Result<T, E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        Self::run_with_commit_version(options, f).map(|(output, _)| output)
+    }
+
+    /// Like [`run_with_options`](#method.run_with_options) but also returns the version
+    /// of the global clock at which the transaction has been committed.
+    /// Transactions that commit later get greater versions.
+    ///
+    /// A transaction that hasn't changed any variable doesn't advance the clock.
+    /// It's committed at the version it started with.
+    pub fn run_with_commit_version<F, T, E>(
+        options: &TxOptions,
+        f: F,
+    ) -> Result<(T, Version), E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        Self::run_attempts(&mut Attempts::new(options), f)
+    }
+
+    /// Like [`run_with_options`](#method.run_with_options) but also returns
+    /// the statistics of the run, whether the transaction has succeeded or not
+    pub fn run_with_stats<F, T, E>(
         options: &TxOptions,
+        f: F,
+    ) -> (Result<T, E>, TxStats)
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        let mut attempts = Attempts::new(options);
+        let result = Self::run_attempts(&mut attempts, f);
+        (result.map(|(output, _)| output), attempts.stats)
+    }
+
+    /// Run a transaction that is attempted exactly once and is guaranteed to commit,
+    /// unless it returns an error. So the transaction may perform side effects
+    /// that can't be retried, e.g. write to a socket.
+    ///
+    /// No other transaction can commit until the irrevocable one is finished,
+    /// so it should be short. Transactions run within it in the same thread
+    /// commit without waiting for it, but if they change the variables it has tracked,
+    /// it fails with [`Error::TooManyTransactionRetryAttempts`].
+    ///
+    /// # Panics
+    ///
+    /// If the transaction calls [`retry`](#method.retry), since it would wait forever
+    /// for a change made by another transaction.
+    pub fn run_irrevocable<F, T, E>(f: F) -> Result<T, E>
+    where
+        F: FnOnce(&Tx) -> Result<T, E>,
+    {
+        let options = TxOptions {
+            attempts: 1,
+            ..Default::default()
+        };
+        let mut attempts = Attempts::new(&options);
+        attempts.irrevocable = true;
+        let mut f = Some(f);
+        let f = |tx: &Tx| {
+            let f = f
+                .take()
+                .expect("BUG: irrevocable transaction must be attempted once");
+            f(tx)
+        };
+        Self::run_attempts(&mut attempts, f).map(|(output, _)| output)
+    }
+
+    fn run_attempts<F, T, E>(
+        attempts: &mut Attempts<'_>,
         mut f: F,
-    ) -> Result<T, E>
+    ) -> Result<(T, Version), E>
     where
         F: FnMut(&Tx) -> Result<T, E>,
     {
-        let TxOptions {
-            attempts,
-            retry_pause,
-            pause_jitter,
-        } = *options;
-        let mut rng = rand::thread_rng();
-
-        for attempt in 0..attempts {
-            if attempt > 0 {
-                let mut pause = retry_pause;
-                if pause_jitter {
-                    pause = pause.mul_f32(rng.gen())
-                }
-                thread::sleep(pause);
+        loop {
+            match attempts.run(&mut f) {
+                Step::Done(result) => return result,
+                Step::Yield => thread::yield_now(),
+                Step::Wait { waiter, until } => waiter.wait(until),
             }
+        }
+    }
 
-            let tx = Self {
-                vars: RefCell::new(BTreeMap::new()),
-            };
-            let result = f(&tx);
-            if let Err(Error::ConcurrentUpdate) = result {
-                continue;
-            }
-            let output = result?;
-            match tx.commit() {
-                CommitStatus::Success => return Ok(output),
-                CommitStatus::Fail => (),
+    /// Like [`run`](#method.run) but returns a future, which can be used with any async executor.
+    ///
+    /// Pauses between attempts and waiting after [`retry`](#method.retry) suspend the task
+    /// instead of blocking the executor thread. The transaction function itself is synchronous,
+    /// so it must not block.
+    pub async fn run_async<F, T, E>(f: F) -> Result<T, E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        Self::run_async_with_options(&Default::default(), f).await
+    }
+
+    /// Like [`run_async`](#method.run_async) but with non-default options
+    pub async fn run_async_with_options<F, T, E>(
+        options: &TxOptions,
+        f: F,
+    ) -> Result<T, E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        let mut attempts = Attempts::new(options);
+        let result = Self::run_attempts_async(&mut attempts, f).await;
+        result.map(|(output, _)| output)
+    }
+
+    /// Async version of [`run_with_stats`](#method.run_with_stats)
+    pub async fn run_async_with_stats<F, T, E>(
+        options: &TxOptions,
+        f: F,
+    ) -> (Result<T, E>, TxStats)
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        let mut attempts = Attempts::new(options);
+        let result = Self::run_attempts_async(&mut attempts, f).await;
+        (result.map(|(output, _)| output), attempts.stats)
+    }
+
+    async fn run_attempts_async<F, T, E>(
+        attempts: &mut Attempts<'_>,
+        mut f: F,
+    ) -> Result<(T, Version), E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        loop {
+            match attempts.run(&mut f) {
+                Step::Done(result) => return result,
+                Step::Yield => timer::yield_now().await,
+                Step::Wait { waiter, until } => waiter.wait_async(until).await,
             }
         }
+    }
 
-        Err(Error::TooManyTransactionRetryAttempts { attempts })
+    /// Install an observer of all transactions run in the process,
+    /// replacing the previous one
+    pub fn set_observer<O>(observer: O)
+    where
+        O: TxObserver + 'static,
+    {
+        observer::set(Some(Arc::new(observer)))
+    }
+
+    /// Remove the observer installed by [`set_observer`](#method.set_observer)
+    pub fn remove_observer() {
+        observer::set(None)
+    }
+
+    /// The commit token must be acquired before reading the version,
+    /// so no variable can be changed after the read version
+    fn new(commit_token: Option<CommitToken>, irrevocable: bool) -> Self {
+        Self {
+            read_version: Version::read(),
+            vars: RefCell::new(BTreeMap::new()),
+            checkpoints: RefCell::new(Vec::new()),
+            hooks: RefCell::new(Hooks::default()),
+            commit_token,
+            irrevocable,
+        }
+    }
+
+    /// Checks if the transaction has been run by [`run_irrevocable`](#method.run_irrevocable)
+    pub fn is_irrevocable(&self) -> bool {
+        self.irrevocable
     }
 
     /// Make the transaction track an STM variable for changes made within the current
@@ -105,28 +372,53 @@ impl Tx {
     /// All the changes made to the same STM variable withing the same transaction are preserved
     /// between the calls of `Tx::track`.
     ///
-    /// Returns an error if there is another alive handle for the variable in the current transaction.
-    pub fn track<'tx, V: StmVar>(
+    /// Every attempt of a transaction observes a consistent snapshot of the variables it tracks,
+    /// as they were at the start of the attempt. If a variable has been changed since then
+    /// by another transaction, the method returns [`Error::ConcurrentUpdate`],
+    /// so the attempt is retried.
+    ///
+    /// Returns an error if there is another alive handle for the variable in the current transaction,
+    /// including a shared one.
+    ///
+    /// Besides STM variables, it accepts other [`Trackable`] types,
+    /// e.g. structs of STM variables with `#[derive(Stm)]`.
+    pub fn track<'tx, V: Trackable, E>(
+        &'tx self,
+        var: &V,
+    ) -> Result<V::Tracked<'tx>, E> {
+        var.track(self)
+    }
+
+    fn track_var<'tx, V: StmVar, E>(
         &'tx self,
         var: &V,
-    ) -> Result<TxRef<'tx, V::TxVar>> {
+    ) -> Result<TxRef<'tx, V::TxVar>, E> {
         let var_id = var.var_id();
         let tx_var = match self.vars.borrow_mut().entry(var_id) {
             Entry::Vacant(entry) => {
+                let tx_var =
+                    var.tx_var(&self.read_version).map_err(Error::lift)?;
+                observer::emit(TxEvent::VarTracked { var_id });
                 entry.insert(TrackedVar::InUse);
-                Box::new(var.tx_var())
+                self.save_initial_state(var_id, &tx_var);
+                Box::new(tx_var)
             }
             Entry::Occupied(mut entry) => {
+                if let TrackedVar::Pending(tx_var) = entry.get_mut() {
+                    tx_var.read_deferred().map_err(Error::lift)?;
+                }
                 match std::mem::replace(entry.get_mut(), TrackedVar::InUse) {
-                    TrackedVar::InUse => {
-                        return Err(Error::TransactionVariableIsInUse(var_id))
-                    }
                     TrackedVar::Pending(tx_var) => tx_var
                         .into_any()
                         .downcast()
                         .expect(
                         "BUG: variable type must be uniquely identified by its ID",
                     ),
+                    tracked_var => {
+                        // Shared handles are still alive
+                        entry.insert(tracked_var);
+                        return Err(Error::TransactionVariableIsInUse(var_id));
+                    }
                 }
             }
         };
@@ -137,35 +429,294 @@ impl Tx {
         })
     }
 
+    /// Like [`track`](#method.track) but returns a read-only handle.
+    /// Like borrows of a `RefCell`, multiple shared handles for the same variable
+    /// can be alive at the same time, e.g. in helper functions that read the same map.
+    ///
+    /// Returns an error if there is an alive handle returned by [`track`](#method.track)
+    /// for the variable in the current transaction.
+    pub fn track_shared<'tx, V: StmVar, E>(
+        &'tx self,
+        var: &V,
+    ) -> Result<TxSharedRef<'tx, V::TxVar>, E> {
+        let var_id = var.var_id();
+        let mut vars = self.vars.borrow_mut();
+        let tx_var: Rc<V::TxVar> = match vars.entry(var_id) {
+            Entry::Vacant(entry) => {
+                let tx_var =
+                    var.tx_var(&self.read_version).map_err(Error::lift)?;
+                observer::emit(TxEvent::VarTracked { var_id });
+                self.save_initial_state(var_id, &tx_var);
+                let tx_var = Rc::new(tx_var);
+                entry.insert(TrackedVar::Shared(tx_var.clone()));
+                tx_var
+            }
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                TrackedVar::InUse => {
+                    return Err(Error::TransactionVariableIsInUse(var_id))
+                }
+                TrackedVar::Shared(tx_var) => tx_var.clone().downcast().expect(
+                    "BUG: variable type must be uniquely identified by its ID",
+                ),
+                TrackedVar::Pending(tx_var) => {
+                    tx_var.read_deferred().map_err(Error::lift)?;
+                    let TrackedVar::Pending(tx_var) =
+                        std::mem::replace(entry.get_mut(), TrackedVar::InUse)
+                    else {
+                        unreachable!()
+                    };
+                    let tx_var: Rc<V::TxVar> = Rc::from(
+                        tx_var.into_any().downcast::<V::TxVar>().expect(
+                        "BUG: variable type must be uniquely identified by its ID",
+                    ),
+                    );
+                    entry.insert(TrackedVar::Shared(tx_var.clone()));
+                    tx_var
+                }
+            },
+        };
+        Ok(TxSharedRef {
+            tx: self,
+            var_id,
+            var: Some(tx_var),
+        })
+    }
+
+    /// Apply a commutative change `f` to the cell, e.g. increment a counter.
+    ///
+    /// Unlike [`track`](#method.track), the method doesn't read the value of the cell,
+    /// so concurrent updates of the cell never cause the transaction to be retried.
+    /// Instead, `f` is applied to the latest committed value of the cell at commit.
+    ///
+    /// If the transaction tracks the cell, either before or after calling this method,
+    /// the in-transaction value of the cell includes the change made by `f`,
+    /// and the cell is validated at commit like any other tracked variable.
+    ///
+    /// Returns an error if there is an alive handle for the cell in the current transaction.
+    pub fn commute<T, F, E>(&self, cell: &StmCell<T>, f: F) -> Result<(), E>
+    where
+        T: Clone + 'static,
+        F: Fn(&mut T) + 'static,
+    {
+        let var_id = cell.var_id();
+        let mut vars = self.vars.borrow_mut();
+        let mut tx_cell = match vars.entry(var_id) {
+            Entry::Vacant(entry) => {
+                let tx_cell = cell.commuted_tx_var(&self.read_version);
+                observer::emit(TxEvent::VarTracked { var_id });
+                self.save_initial_state(var_id, &tx_cell);
+                entry.insert(TrackedVar::InUse);
+                Box::new(tx_cell)
+            }
+            Entry::Occupied(mut entry) => {
+                match std::mem::replace(entry.get_mut(), TrackedVar::InUse) {
+                    TrackedVar::Pending(tx_var) => tx_var
+                        .into_any()
+                        .downcast::<TxCell<T>>()
+                        .expect(
+                        "BUG: variable type must be uniquely identified by its ID",
+                    ),
+                    tracked_var => {
+                        entry.insert(tracked_var);
+                        return Err(Error::TransactionVariableIsInUse(var_id));
+                    }
+                }
+            }
+        };
+        tx_cell.commute(Rc::new(f));
+        vars.insert(var_id, TrackedVar::Pending(tx_cell));
+        Ok(())
+    }
+
+    /// A rollback must bring a variable back to the state it had
+    /// when the transaction started tracking it
+    fn save_initial_state(&self, var_id: StmVarId, tx_var: &dyn TxVar) {
+        for checkpoint in self.checkpoints.borrow_mut().iter_mut() {
+            checkpoint.vars.insert(var_id, tx_var.snapshot());
+        }
+    }
+
+    /// Register a function to be called once after the transaction is committed,
+    /// e.g. to send a notification about the changes. The function is called
+    /// after the variables are unlocked, in the order of registration.
+    ///
+    /// If the attempt of the transaction fails and the transaction is retried,
+    /// the function is dropped without being called.
+    pub fn on_commit<F>(&self, f: F)
+    where
+        F: FnOnce() + 'static,
+    {
+        self.hooks.borrow_mut().on_commit.push(Box::new(f))
+    }
+
+    /// Register a function to be called once if the transaction finally fails,
+    /// i.e. it's aborted, returns an error or runs out of attempts.
+    ///
+    /// If the attempt of the transaction fails and the transaction is retried,
+    /// the function is dropped without being called. The function is kept
+    /// until the next attempt starts, so it must be `Send` to let an async run
+    /// wait for the attempt on another thread.
+    pub fn on_abort<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.hooks.borrow_mut().on_abort.push(Box::new(f))
+    }
+
+    /// Run the `first` branch of the transaction, and if it calls [`retry`](#method.retry),
+    /// run the `second` branch instead. All the changes made by the `first` branch,
+    /// including the registered hooks, are rolled back before running the `second` one.
+    /// However, the variables tracked by the `first` branch are still validated at commit
+    /// and taken into account if the whole transaction is retried.
+    ///
+    /// Returns an error if there is an alive handle for any variable tracked by the transaction.
+    pub fn or_else<T, E, F, G>(&self, first: F, second: G) -> Result<T, E>
+    where
+        F: FnOnce(&Tx) -> Result<T, E>,
+        G: FnOnce(&Tx) -> Result<T, E>,
+    {
+        self.checkpoint()?;
+        match first(self) {
+            Err(Error::TransactionRetry) => {
+                self.rollback();
+                second(self)
+            }
+            result => {
+                self.checkpoints.borrow_mut().pop();
+                result
+            }
+        }
+    }
+
+    /// Run a nested transaction within the current one. If the nested transaction
+    /// returns an error, only the changes made by it are rolled back,
+    /// together with the hooks it has registered,
+    /// and the error is returned to the outer transaction.
+    ///
+    /// Returns an error if there is an alive handle for any variable tracked by the transaction.
+    pub fn nested<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&Tx) -> Result<T, E>,
+    {
+        self.checkpoint()?;
+        let result = f(self);
+        if result.is_err() {
+            self.rollback();
+        } else {
+            self.checkpoints.borrow_mut().pop();
+        }
+        result
+    }
+
+    fn checkpoint<E>(&self) -> Result<(), E> {
+        let vars = self
+            .vars
+            .borrow()
+            .iter()
+            .map(|(var_id, tracked_var)| match tracked_var {
+                TrackedVar::Pending(tx_var) => Ok((*var_id, tx_var.snapshot())),
+                _ => Err(Error::TransactionVariableIsInUse(*var_id)),
+            })
+            .collect::<Result<_, E>>()?;
+        let hooks = self.hooks.borrow();
+        self.checkpoints.borrow_mut().push(Checkpoint {
+            vars,
+            commit_hooks: hooks.on_commit.len(),
+            abort_hooks: hooks.on_abort.len(),
+        });
+        Ok(())
+    }
+
+    fn rollback(&self) {
+        let checkpoint = self
+            .checkpoints
+            .borrow_mut()
+            .pop()
+            .expect("BUG: rollback must follow a checkpoint");
+        let mut hooks = self.hooks.borrow_mut();
+        hooks.on_commit.truncate(checkpoint.commit_hooks);
+        hooks.on_abort.truncate(checkpoint.abort_hooks);
+        let mut vars = self.vars.borrow_mut();
+        for (var_id, tx_var) in checkpoint.vars {
+            let tx_var_status =
+                vars.insert(var_id, TrackedVar::Pending(tx_var));
+            let Some(TrackedVar::Pending(_)) = tx_var_status else {
+                panic!("BUG: there must be no `TxRef` around for this transaction");
+            };
+        }
+    }
+
     fn commit(mut self) -> CommitStatus {
+        // Every value observed by the transaction has been validated against
+        // the read version, so a read-only transaction can be committed
+        // without locking the variables.
+        if self.pending_vars().all(|tx_var| !tx_var.has_changes()) {
+            return CommitStatus::Success(self.read_version);
+        }
+        let _shared_token = (self.commit_token.is_none()
+            && !CommitToken::is_held())
+        .then(|| COMMIT_TOKEN.read());
         // The variables will be locked in the ascending order of their IDs.
         let locked_vars: Vec<_> = self
             .vars
             .get_mut()
-            .values_mut()
-            .map(|tracked_var| {
-                let TrackedVar::Pending(tx_var) = tracked_var else {
-                    panic!("BUG: there must be no `TxRef` around for this transaction");
-                };
-                tx_var.lock()
+            .iter_mut()
+            .map(|(var_id, tracked_var)| {
+                (*var_id, pending_var(tracked_var).lock())
             })
             .collect();
-        for var in &locked_vars {
+        for (var_id, var) in &locked_vars {
             if !var.can_commit() {
-                return CommitStatus::Fail;
+                return CommitStatus::Fail(*var_id);
             }
         }
-        for mut var in locked_vars {
-            var.commit()
+        let write_version = Version::write();
+        for (_, mut var) in locked_vars {
+            var.commit(&write_version)
         }
-        CommitStatus::Success
+        CommitStatus::Success(write_version)
+    }
+
+    /// Returns a waiter to be notified when one of the tracked variables is changed,
+    /// or `None` if some of them have already changed
+    fn subscribe_for_change(mut self) -> Option<Arc<Waiter>> {
+        let waiter = Waiter::new();
+        let subscribed =
+            self.pending_vars().all(|tx_var| tx_var.subscribe(&waiter));
+        subscribed.then_some(waiter)
     }
 
-    /// Abort current transaction and prevent it from futher retrying
+    /// IDs of the variables changed by a finished attempt of the transaction
+    fn changed_vars(&mut self) -> Vec<StmVarId> {
+        self.vars
+            .get_mut()
+            .iter_mut()
+            .filter_map(|(var_id, tracked_var)| {
+                pending_var(tracked_var).has_changes().then_some(*var_id)
+            })
+            .collect()
+    }
+
+    /// Variables of a finished attempt of the transaction
+    fn pending_vars(&mut self) -> impl Iterator<Item = &mut Box<dyn TxVar>> {
+        self.vars.get_mut().values_mut().map(pending_var)
+    }
+
+    /// Abort current transaction and prevent it from futher retrying.
+    /// Transactions that abort with custom errors use [`abort_with`](#method.abort_with).
     pub fn abort() -> Result<(), ()> {
         Self::abort_with(())
     }
 
+    /// Block the transaction until any of the variables it has tracked so far is changed
+    /// by another transaction, and then run it again from the beginning.
+    /// This method should be used when the transaction can't proceed, e.g. a queue is empty.
+    ///
+    /// If the transaction hasn't tracked any variable, it will be blocked forever.
+    pub fn retry<E>() -> Result<(), E> {
+        Err(Error::TransactionRetry)
+    }
+
     /// Aborth transaction with the given error.
     /// This method should be used for propagating custom errors out of a transaction.
     pub fn abort_with<E>(error: E) -> Result<(), E> {
@@ -173,23 +724,379 @@ impl Tx {
     }
 }
 
-/// Implementors must track the original version of variable's value
+fn pending_var(tracked_var: &mut TrackedVar) -> &mut Box<dyn TxVar> {
+    let TrackedVar::Pending(tx_var) = tracked_var else {
+        panic!("BUG: there must be no `TxRef` around for this transaction");
+    };
+    tx_var
+}
+
+/// What a transaction runner should do after an attempt of a transaction
+enum Step<T, E> {
+    Done(Result<(T, Version), E>),
+    /// Yield the thread or the task before the next attempt
+    Yield,
+    /// Wait until the waiter is notified or the time is up,
+    /// and then run the transaction again
+    Wait {
+        waiter: Arc<Waiter>,
+        until: Option<Instant>,
+    },
+}
+
+/// Attempts to complete a transaction, shared by the sync and async runners
+struct Attempts<'a> {
+    options: &'a TxOptions,
+    attempt: usize,
+    previous_pause: Duration,
+    /// The start of the current pause between attempts
+    paused_at: Option<Instant>,
+    /// Attempts hold the commit token, and they can't be retried
+    irrevocable: bool,
+    /// See [`ContentionContext::ticket`]
+    ticket: Option<u64>,
+    contender: Option<ContenderGuard>,
+    /// Hooks of the last failed attempt, which are called if the run finally
+    /// fails before the next attempt starts, e.g. during a pause
+    abort_hooks: Vec<AbortHook>,
+    stats: TxStats,
+}
+
+impl<'a> Attempts<'a> {
+    fn new(options: &'a TxOptions) -> Self {
+        Self {
+            options,
+            attempt: 0,
+            previous_pause: Duration::ZERO,
+            paused_at: None,
+            irrevocable: false,
+            ticket: None,
+            contender: None,
+            abort_hooks: Vec::new(),
+            stats: TxStats::default(),
+        }
+    }
+
+    fn run<F, T, E>(&mut self, f: &mut F) -> Step<T, E>
+    where
+        F: FnMut(&Tx) -> Result<T, E>,
+    {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.stats.pause_time += paused_at.elapsed();
+        }
+        if self.attempt >= self.options.attempts {
+            return self.too_many_attempts();
+        }
+        if let Some(err) = self.interruption() {
+            return self.fail(err);
+        }
+        let manager = &*self.options.contention_manager;
+        // Only a transaction that has failed because of concurrent updates
+        // may wait for the contenders that have failed because of them
+        if !manager.never_waits() && !self.stats.conflicts.is_empty() {
+            let context = self.contention_context();
+            let contender = contention::contender_to_wait_for(
+                manager,
+                &self.stats.conflicts,
+                &context,
+            );
+            if let Some(waiter) = contender {
+                return self.wait(waiter, None);
+            }
+        }
+        let pessimistic = self.irrevocable
+            || self
+                .options
+                .pessimistic_after
+                .is_some_and(|failed_attempts| self.attempt >= failed_attempts);
+        let commit_token = pessimistic.then(CommitToken::acquire).flatten();
+        let mut tx = Tx::new(commit_token, self.irrevocable);
+        self.stats.attempts += 1;
+        // The previous attempt won't be the last one
+        self.abort_hooks.clear();
+        if pessimistic {
+            self.stats.pessimistic_attempts += 1;
+        }
+        observer::emit(TxEvent::AttemptStarted {
+            attempt: self.stats.attempts,
+        });
+        let result = f(&tx);
+        self.stats.tracked_vars = tx.vars.borrow().len();
+        let hooks = tx.hooks.take();
+        match result {
+            Err(Error::ConcurrentUpdate(var_id)) => {
+                observer::emit(TxEvent::ConflictDetected { var_id });
+                drop(tx);
+                return self.next_attempt(var_id, hooks);
+            }
+            Err(Error::TransactionRetry) if tx.is_irrevocable() => {
+                panic!("Irrevocable transaction can't be retried")
+            }
+            Err(Error::TransactionRetry) => {
+                self.abort_hooks = hooks.on_abort;
+                // Other transactions must not wait for the contender
+                // while it waits for their changes
+                self.contender = None;
+                // The attempt is run again right away
+                // if a tracked variable has already changed
+                return match tx.subscribe_for_change() {
+                    Some(waiter) => self.wait(waiter, None),
+                    None => Step::Yield,
+                };
+            }
+            _ => (),
+        }
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                // The commit token is released before calling the hooks
+                drop(tx);
+                self.abort_hooks = hooks.on_abort;
+                return self.fail(err);
+            }
+        };
+        // The commit would make a contender that takes precedence fail again.
+        // An attempt that holds the commit token can't make others fail.
+        if tx.commit_token.is_none() && !manager.never_waits() {
+            let changed_vars = tx.changed_vars();
+            let conflict = contention::conflict_with_contender(
+                manager,
+                &changed_vars,
+                || self.contention_context(),
+            );
+            if let Some(var_id) = conflict {
+                observer::emit(TxEvent::CommitFailed { var_id });
+                drop(tx);
+                return self.next_attempt(var_id, hooks);
+            }
+        }
+        match tx.commit() {
+            CommitStatus::Success(version) => {
+                observer::emit(TxEvent::Committed {
+                    version: version.clone(),
+                });
+                run_hooks(hooks.on_commit);
+                Step::Done(Ok((output, version)))
+            }
+            CommitStatus::Fail(var_id) => {
+                observer::emit(TxEvent::CommitFailed { var_id });
+                self.next_attempt(var_id, hooks)
+            }
+        }
+    }
+
+    /// Hooks of the failed attempt are called only if there will be no more attempts
+    fn next_attempt<T, E>(
+        &mut self,
+        conflicting_var: StmVarId,
+        hooks: Hooks,
+    ) -> Step<T, E> {
+        self.stats.conflicts.push(conflicting_var);
+        self.attempt += 1;
+        self.abort_hooks = hooks.on_abort;
+        if self.attempt >= self.options.attempts {
+            return self.too_many_attempts();
+        }
+        if let Some(err) = self.interruption() {
+            return self.fail(err);
+        }
+        // A transaction that has failed because of concurrent updates
+        // lets other transactions wait for it until the end of the run
+        if !self.options.contention_manager.never_waits() {
+            let context = self.contention_context();
+            let conflicts = &self.stats.conflicts;
+            match &self.contender {
+                Some(contender) => contender.update(context, conflicts),
+                None => {
+                    self.contender =
+                        Some(contention::register(context, conflicts))
+                }
+            }
+        }
+        let backoff = self.options.retry_policy.backoff(&RetryContext {
+            attempt: self.attempt,
+            previous_pause: self.previous_pause,
+            conflicting_var,
+        });
+        match backoff {
+            Backoff::Pause(pause) => {
+                self.previous_pause = pause;
+                self.paused_at = Some(Instant::now());
+                self.wait(Waiter::new(), Some(pause))
+            }
+            Backoff::Yield => {
+                self.previous_pause = Duration::ZERO;
+                Step::Yield
+            }
+        }
+    }
+
+    /// The wait is interrupted by the deadline or the cancellation of the transaction
+    fn wait<T, E>(
+        &mut self,
+        waiter: Arc<Waiter>,
+        pause: Option<Duration>,
+    ) -> Step<T, E> {
+        if let Some(token) = &self.options.cancellation {
+            if !token.subscribe(&waiter) {
+                return self.fail(Error::Cancelled);
+            }
+        }
+        // A pause too long to be represented ends only with the deadline
+        let pause_end =
+            pause.and_then(|pause| Instant::now().checked_add(pause));
+        let until = match (pause_end, self.options.deadline) {
+            (Some(pause_end), Some(deadline)) => Some(pause_end.min(deadline)),
+            (pause_end, deadline) => pause_end.or(deadline),
+        };
+        Step::Wait { waiter, until }
+    }
+
+    fn contention_context(&mut self) -> ContentionContext {
+        ContentionContext {
+            priority: self.options.priority,
+            failed_attempts: self.attempt,
+            ticket: *self.ticket.get_or_insert_with(contention::next_ticket),
+        }
+    }
+
+    fn interruption<E>(&self) -> Option<Error<E>> {
+        let TxOptions {
+            deadline,
+            cancellation,
+            ..
+        } = self.options;
+        if cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Some(Error::Cancelled);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(Error::DeadlineExceeded);
+        }
+        None
+    }
+
+    fn too_many_attempts<T, E>(&mut self) -> Step<T, E> {
+        let conflicts = Conflict::from_var_ids(&self.stats.conflicts);
+        self.fail(Error::TooManyTransactionRetryAttempts {
+            attempts: self.options.attempts,
+            conflicts,
+        })
+    }
+
+    /// The run of the transaction has finally failed
+    fn fail<T, E>(&mut self, err: Error<E>) -> Step<T, E> {
+        run_hooks(std::mem::take(&mut self.abort_hooks));
+        observer::emit(TxEvent::Aborted);
+        Step::Done(Err(err))
+    }
+}
+
+fn run_hooks<H: FnOnce()>(hooks: Vec<H>) {
+    for hook in hooks {
+        hook()
+    }
+}
+
+/// Something that can be tracked by a transaction with [`Tx::track`],
+/// e.g. an [`StmVar`] or a struct of them.
+///
+/// With the `derive` feature, `#[derive(Stm)]` implements the trait for a struct
+/// whose fields are STM variables. For `struct Account`, it generates
+/// `TxAccount<'tx>` that keeps the handles of all the fields,
+/// with an accessor and a mutable accessor for each field.
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use naive_stm::{Result, Stm, StmCell, StmMap, Tx};
+///
+/// #[derive(Stm)]
+/// struct Account {
+///     balance: StmCell<u64>,
+///     limits: StmMap<String, u64>,
+/// }
+///
+/// let account = Account {
+///     balance: StmCell::new(100),
+///     limits: StmMap::new(),
+/// };
+/// Tx::run(|tx| -> Result {
+///     let mut account = tx.track(&account)?;
+///     *account.balance_mut().get_mut() -= 30;
+///     account.limits_mut().insert("daily".to_owned(), 500);
+///     Ok(())
+/// })
+/// .unwrap();
+/// # }
+/// ```
+pub trait Trackable {
+    /// A handle that gives access to the in-transaction state
+    type Tracked<'tx>;
+
+    fn track<'tx, E>(&self, tx: &'tx Tx) -> Result<Self::Tracked<'tx>, E>;
+}
+
+impl<V: StmVar> Trackable for V {
+    type Tracked<'tx> = TxRef<'tx, V::TxVar>;
+
+    fn track<'tx, E>(&self, tx: &'tx Tx) -> Result<Self::Tracked<'tx>, E> {
+        tx.track_var(self)
+    }
+}
+
+/// In-transaction state of an [`StmVar`](crate::StmVar).
+///
+/// Implementors must track the read version of the transaction, and they must
+/// keep the changes made by the transaction private until the commit.
 pub trait TxVar: 'static {
-    /// This method is called in the commit phase of a transaction.
+    /// This method is called in the commit phase of a transaction,
+    /// for all the tracked variables in the ascending order of their IDs.
+    ///
+    /// The shared state of the variable must stay locked until the returned value
+    /// is dropped: exclusively if the variable has changes, otherwise the lock may be shared.
     /// [`LockedTxVar`] is responsible for checking whether the variable's value
     /// has changed while the transaction was running.
     fn lock(&mut self) -> Box<dyn LockedTxVar + '_>;
 
+    /// Checks if the transaction has changed the variable.
+    /// If no variable has changes, the transaction is committed without locking them.
+    fn has_changes(&self) -> bool;
+
+    /// Completes the reads deferred until the variable is tracked
+    /// by the transaction, e.g. of a cell that has only been commuted so far
+    fn read_deferred(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    /// Registers the waiter to be notified when the STM variable is changed,
+    /// e.g. by [`VersionedValue::subscribe`](crate::VersionedValue::subscribe).
+    /// Returns `false` if the variable has already changed since it was tracked.
+    fn subscribe(&self, waiter: &Arc<Waiter>) -> bool;
+
+    /// Copies the in-transaction state of the variable, which is restored
+    /// if a part of the transaction is rolled back, e.g. by [`Tx::nested`].
+    /// The copy must refer to the same shared state.
+    fn snapshot(&self) -> Box<dyn TxVar>;
+
+    /// Must return `self`, which is used for downcasting
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
 
+/// A [`TxVar`] whose shared state is locked by a committing transaction
 pub trait LockedTxVar {
-    /// Checks if the variable's value has changed since the first read
+    /// Checks if the variable's value has changed since the transaction started.
+    /// It's called for all the variables of the transaction after all of them are locked.
     fn can_commit(&self) -> bool;
 
     /// Writes data generated by a transaction to a shared transaction variable,
     /// thus making the changes visible to other transactions.
-    fn commit(&mut self);
+    /// It's called only if all the variables of the transaction can be committed.
+    ///
+    /// The written value must be stamped with `write_version`,
+    /// e.g. by [`VersionedValue::update_version`](crate::VersionedValue::update_version).
+    fn commit(&mut self, write_version: &Version);
 }
 
 /// A wrapper for an STM variable that is tracked by a transaction.
@@ -249,16 +1156,70 @@ impl<T: TxVar + fmt::Debug> fmt::Debug for TxRef<'_, T> {
     }
 }
 
+/// A read-only handle for an STM variable that is tracked by a transaction,
+/// see [`Tx::track_shared`]
+pub struct TxSharedRef<'tx, T>
+where
+    T: TxVar,
+{
+    tx: &'tx Tx,
+    var_id: StmVarId,
+    var: Option<Rc<T>>,
+}
+
+impl<T: TxVar> TxSharedRef<'_, T> {
+    fn get_var(&self) -> &T {
+        self.var.as_deref().expect(NO_VAR_ERROR_MSG)
+    }
+}
+
+impl<'tx, T: TxVar> Drop for TxSharedRef<'tx, T> {
+    fn drop(&mut self) {
+        let Self { tx, var_id, var } = self;
+        let var = var.take().expect(NO_VAR_ERROR_MSG);
+        let mut vars = tx.vars.borrow_mut();
+        let Some(tracked_var) = vars.get_mut(var_id) else {
+            panic!("BUG: transaction has not been tracking the variable `{var_id:?}`")
+        };
+        // The last handle flushes the variable back to the transaction
+        if Rc::strong_count(&var) == 2 {
+            let TrackedVar::Shared(_) =
+                std::mem::replace(tracked_var, TrackedVar::InUse)
+            else {
+                panic!("BUG: variable `{var_id:?}` must be shared by the transaction")
+            };
+            let var = Rc::try_unwrap(var)
+                .ok()
+                .expect("BUG: the last shared handle must own the variable");
+            *tracked_var = TrackedVar::Pending(Box::new(var));
+        }
+    }
+}
+
+impl<T: TxVar> Deref for TxSharedRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.get_var()
+    }
+}
+
+impl<T: TxVar + fmt::Debug> fmt::Debug for TxSharedRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TxSharedRef<{:?}>({:?})", self.get_var(), self.var_id)
+    }
+}
+
 /// A helper to create tracked transaction variables from STM variables in the current block scope.
 ///
 /// # Examples
 ///
 /// ```
-/// use naive_stm::{Tx, StmCell, StmQueue, track};
+/// use naive_stm::{Tx, Result, StmCell, StmQueue, track};
 ///
 /// let cell = StmCell::new(777);
 /// let queue = StmQueue::from_iter([23]);
-/// Tx::run(|tx| {
+/// Tx::run(|tx| -> Result {
 ///     track!(tx, cell, queue);
 ///     *cell.get_mut() = queue.pop()?.unwrap();
 ///     assert_eq!(23, *cell.get());
@@ -275,3 +1236,118 @@ macro_rules! track {
         )+
     };
 }
+
+/// Runs a transaction that tracks the given STM variables,
+/// and returns its result wrapped in [`Result`](crate::Result).
+///
+/// The variables are listed before `=>`, optionally after the options and `;`.
+/// Each of them is an expression that is tracked with [`Tx::track`].
+/// The handle is named after the last identifier in the expression,
+/// e.g. `field` for `b.field`, unless it's renamed with `as`.
+/// The block is run as the body of [`Tx::run`], or of [`Tx::run_with_options`]
+/// if the options are given, and the value of the block is wrapped in `Ok`.
+/// The transaction itself isn't accessible from the block, but [`Tx::retry`]
+/// and [`Tx::abort`] can be used as usual.
+///
+/// # Examples
+///
+/// ```
+/// use naive_stm::{atomically, StmCell, StmQueue, TxOptions};
+///
+/// struct Inbox {
+///     messages: StmQueue<String>,
+/// }
+///
+/// let cell = StmCell::new(0);
+/// let inbox = Inbox {
+///     messages: StmQueue::from_iter(["hello".to_owned()]),
+/// };
+/// let total = &StmCell::new(0);
+/// let options = TxOptions {
+///     attempts: 3,
+///     ..Default::default()
+/// };
+/// let message = atomically!(options; cell, inbox.messages, &total as t => {
+///     **cell += 1;
+///     **t += 1;
+///     messages.pop()?
+/// });
+/// assert_eq!(message.unwrap(), Some("hello".to_owned()));
+/// assert_eq!(atomically!(cell => **cell).unwrap(), 1);
+/// ```
+#[macro_export]
+macro_rules! atomically {
+    ($options:expr; $($vars:tt)*) => {
+        $crate::__atomically!(@vars [$options] [] [] [] $($vars)*)
+    };
+    ($($vars:tt)*) => {
+        $crate::__atomically!(@vars [] [] [] [] $($vars)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __atomically {
+    // The state is [options] [(expr) name, ...] [current expr] [last ident]
+    (@vars $options:tt [$($vars:tt)*] [$($expr:tt)+] $last:tt
+        as $name:ident, $($rest:tt)*) => {
+        $crate::__atomically!(
+            @vars $options [$($vars)* ($($expr)+) $name,] [] [] $($rest)*
+        )
+    };
+    (@vars $options:tt [$($vars:tt)*] [$($expr:tt)+] $last:tt
+        as $name:ident => $body:expr) => {
+        $crate::__atomically!(
+            @run $options [$($vars)* ($($expr)+) $name,] $body
+        )
+    };
+    (@vars $options:tt [$($vars:tt)*] [$($expr:tt)+] [$name:ident],
+        $($rest:tt)*) => {
+        $crate::__atomically!(
+            @vars $options [$($vars)* ($($expr)+) $name,] [] [] $($rest)*
+        )
+    };
+    (@vars $options:tt [$($vars:tt)*] [$($expr:tt)+] [$name:ident]
+        => $body:expr) => {
+        $crate::__atomically!(
+            @run $options [$($vars)* ($($expr)+) $name,] $body
+        )
+    };
+    (@vars $options:tt [$($vars:tt)*] [] [] => $body:expr) => {
+        $crate::__atomically!(@run $options [$($vars)*] $body)
+    };
+    (@vars $options:tt $vars:tt [$($expr:tt)*] $last:tt
+        $ident:ident $($rest:tt)*) => {
+        $crate::__atomically!(
+            @vars $options $vars [$($expr)* $ident] [$ident] $($rest)*
+        )
+    };
+    (@vars $options:tt $vars:tt [$($expr:tt)*] $last:tt
+        $token:tt $($rest:tt)*) => {
+        $crate::__atomically!(
+            @vars $options $vars [$($expr)* $token] $last $($rest)*
+        )
+    };
+    (@run [] [$(($($expr:tt)+) $name:ident,)*] $body:expr) => {
+        $crate::Tx::run(|tx| -> $crate::Result<_> {
+            $crate::__atomically!(@track tx $(($($expr)+) $name,)*);
+            ::std::result::Result::Ok($body)
+        })
+    };
+    (@run [$options:expr] [$(($($expr:tt)+) $name:ident,)*] $body:expr) => {
+        $crate::Tx::run_with_options(&$options, |tx| -> $crate::Result<_> {
+            $crate::__atomically!(@track tx $(($($expr)+) $name,)*);
+            ::std::result::Result::Ok($body)
+        })
+    };
+    (@track $tx:ident $(($($expr:tt)+) $name:ident,)*) => {
+        #[allow(unused_imports)]
+        use $crate::Trackable as _;
+        $(
+            // The method call dereferences the expression if needed,
+            // e.g. a reference to a struct that derives `Stm`
+            #[allow(unused_mut)]
+            let mut $name = ($($expr)+).track($tx)?;
+        )*
+    };
+}
@@ -1,20 +1,28 @@
 use crate::{
     transaction::{LockedTxVar, TxVar},
     variable::{
-        self, LockGuard, LockedVersionedValue, SharedVersionedValue, StmVar,
-        StmVarId, Version, VersionedValue,
+        self, LabelGuard, LockGuard, LockedVersionedValue,
+        SharedVersionedValue, StmVar, StmVarId, Version, VersionedValue,
+        Waiter,
     },
+    Result,
 };
 use std::{
     any::{self, Any},
+    cell::Cell,
+    convert::Infallible,
     fmt,
     ops::{Deref, DerefMut},
+    rc::Rc,
+    sync::Arc,
 };
 
 /// Atomic single element container
 #[derive(Clone)]
 pub struct StmCell<T> {
     var_id: StmVarId,
+    /// Keeps the label registered while the variable is alive
+    _label: Option<Arc<LabelGuard>>,
     value: SharedVersionedValue<T>,
 }
 
@@ -22,9 +30,25 @@ impl<T> StmCell<T> {
     pub fn new(value: T) -> Self {
         Self {
             var_id: StmVarId::new(),
+            _label: None,
             value: VersionedValue::new_in_shared_lock(value),
         }
     }
+
+    /// Creates a cell with a human-readable label, e.g. `"balance:alice"`,
+    /// which is shown in errors and debug output instead of a bare ID
+    pub fn with_label(label: impl Into<Arc<str>>, value: T) -> Self {
+        let (var_id, label) = StmVarId::with_label(label);
+        Self {
+            var_id,
+            _label: Some(label),
+            value: VersionedValue::new_in_shared_lock(value),
+        }
+    }
+
+    pub fn var_id(&self) -> StmVarId {
+        self.var_id
+    }
 }
 
 impl<T> StmVar for StmCell<T>
@@ -37,44 +61,85 @@ where
         self.var_id
     }
 
-    fn tx_var(&self) -> Self::TxVar {
-        let ver_value = self.value.read();
-        let initial_version = ver_value.version.clone();
-        let tx_value = ver_value.data.clone();
-        drop(ver_value);
+    fn tx_var(
+        &self,
+        read_version: &Version,
+    ) -> Result<Self::TxVar, Infallible> {
+        let tx_value = read_value(self.var_id, &self.value, read_version)?;
+        Ok(TxCell {
+            var_id: self.var_id,
+            read_version: read_version.clone(),
+            value: variable::clone_shared_lock(&self.value),
+            tx_value: Some(tx_value),
+            read: Rc::new(Cell::new(true)),
+            write_tx_value: false,
+            commutes: Vec::new(),
+        })
+    }
+}
+
+impl<T> StmCell<T> {
+    /// Creates a transaction variable that hasn't read the value of the cell
+    pub(crate) fn commuted_tx_var(&self, read_version: &Version) -> TxCell<T> {
         TxCell {
-            initial_version,
+            var_id: self.var_id,
+            read_version: read_version.clone(),
             value: variable::clone_shared_lock(&self.value),
-            tx_value,
+            tx_value: None,
+            read: Rc::new(Cell::new(false)),
             write_tx_value: false,
+            commutes: Vec::new(),
         }
     }
 }
 
+/// Reads the value of the cell as of `read_version`
+fn read_value<T: Clone>(
+    var_id: StmVarId,
+    value: &SharedVersionedValue<T>,
+    read_version: &Version,
+) -> Result<T, Infallible> {
+    let ver_value = value.read();
+    ver_value.read(var_id, read_version).cloned()
+}
+
 impl<T> fmt::Debug for StmCell<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "StmCell<{}>({:?})", any::type_name::<T>(), self.var_id)
     }
 }
 
+type Commute<T> = Rc<dyn Fn(&mut T)>;
+
 /// A handle for [`StmCell`] tracked by a transaction
 pub struct TxCell<T> {
-    initial_version: Version,
+    var_id: StmVarId,
+    read_version: Version,
     value: SharedVersionedValue<T>,
-    tx_value: T,
+    /// `None` if the cell has only been commuted by the transaction
+    tx_value: Option<T>,
+    /// Whether the transaction has read the value of the cell. It's shared
+    /// by the snapshots, so the read is validated at commit even if the part
+    /// of the transaction that has read the value is rolled back.
+    read: Rc<Cell<bool>>,
     write_tx_value: bool,
+    /// Changes to be applied to the latest value of the cell at commit
+    commutes: Vec<Commute<T>>,
 }
 
+static NO_VALUE_ERROR_MSG: &str =
+    "BUG: a tracked cell must have read its value";
+
 impl<T> TxCell<T> {
     /// Reference to the in-transaction value of the cell
     pub fn get(&self) -> &T {
-        &self.tx_value
+        self.tx_value.as_ref().expect(NO_VALUE_ERROR_MSG)
     }
 
     /// Mutable reference to the in-transaction value of the cell
     pub fn get_mut(&mut self) -> &mut T {
         self.write_tx_value = true;
-        &mut self.tx_value
+        self.tx_value.as_mut().expect(NO_VALUE_ERROR_MSG)
     }
 
     /// Takes the value out of the cell, leaving the default value of `T`
@@ -84,6 +149,18 @@ impl<T> TxCell<T> {
     {
         std::mem::take(self.get_mut())
     }
+
+    /// If the cell has been read by the transaction, the change is applied
+    /// to the in-transaction value. Otherwise, it's deferred until the commit.
+    pub(crate) fn commute(&mut self, f: Commute<T>) {
+        match &mut self.tx_value {
+            Some(tx_value) => {
+                f(tx_value);
+                self.write_tx_value = true;
+            }
+            None => self.commutes.push(f),
+        }
+    }
 }
 
 impl<T> Deref for TxCell<T> {
@@ -106,23 +183,68 @@ impl<T> fmt::Debug for TxCell<T> {
     }
 }
 
-impl<T: 'static> TxVar for TxCell<T> {
+impl<T: Clone + 'static> TxVar for TxCell<T> {
+    fn has_changes(&self) -> bool {
+        self.write_tx_value || !self.commutes.is_empty()
+    }
+
+    fn read_deferred(&mut self) -> Result<(), Infallible> {
+        if self.tx_value.is_some() {
+            return Ok(());
+        }
+        let mut tx_value =
+            read_value(self.var_id, &self.value, &self.read_version)?;
+        self.read.set(true);
+        // The transaction observes its own deferred changes
+        for f in self.commutes.drain(..) {
+            f(&mut tx_value);
+            self.write_tx_value = true;
+        }
+        self.tx_value = Some(tx_value);
+        Ok(())
+    }
+
     fn lock(&mut self) -> Box<dyn LockedTxVar + '_> {
+        let has_changes = self.has_changes();
         let Self {
-            initial_version,
+            read_version,
             value,
             tx_value,
-            write_tx_value,
+            read,
+            commutes,
+            ..
         } = self;
-        let value = if *write_tx_value {
+        let value = if has_changes {
             LockGuard::Write(value.write())
         } else {
             LockGuard::Read(value.read())
         };
         Box::new(LockedTxCell {
-            initial_version: initial_version.clone(),
+            read_version: read_version.clone(),
             value,
             tx_value,
+            read: read.get(),
+            commutes,
+        })
+    }
+
+    fn subscribe(&self, waiter: &Arc<Waiter>) -> bool {
+        if !self.read.get() {
+            // The transaction doesn't depend on the value of the cell
+            return true;
+        }
+        self.value.write().subscribe(&self.read_version, waiter)
+    }
+
+    fn snapshot(&self) -> Box<dyn TxVar> {
+        Box::new(Self {
+            var_id: self.var_id,
+            read_version: self.read_version.clone(),
+            value: variable::clone_shared_lock(&self.value),
+            tx_value: self.tx_value.clone(),
+            read: Rc::clone(&self.read),
+            write_tx_value: self.write_tx_value,
+            commutes: self.commutes.clone(),
         })
     }
 
@@ -132,22 +254,32 @@ impl<T: 'static> TxVar for TxCell<T> {
 }
 
 struct LockedTxCell<'a, T> {
-    initial_version: Version,
+    read_version: Version,
     value: LockedVersionedValue<'a, T>,
-    tx_value: &'a mut T,
+    tx_value: &'a mut Option<T>,
+    read: bool,
+    commutes: &'a [Commute<T>],
 }
 
 impl<'a, T> LockedTxVar for LockedTxCell<'a, T> {
     fn can_commit(&self) -> bool {
-        &self.initial_version == self.value.current_version()
+        // A cell that has only been commuted doesn't conflict with anything
+        !self.read || self.value.current_version() <= &self.read_version
     }
 
-    fn commit(&mut self) {
+    fn commit(&mut self, write_version: &Version) {
         let value = match &mut self.value {
             LockGuard::Read(_) => return,
             LockGuard::Write(value) => value,
         };
-        value.version.increment();
-        std::mem::swap(self.tx_value, &mut value.data)
+        value.update_version(write_version);
+        match self.tx_value {
+            Some(tx_value) => std::mem::swap(tx_value, &mut value.data),
+            None => {
+                for f in self.commutes {
+                    f(&mut value.data)
+                }
+            }
+        }
     }
 }
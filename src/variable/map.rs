@@ -1,25 +1,67 @@
 use crate::{
     transaction::{LockedTxVar, TxVar},
     variable::{
-        self, LockGuard, LockedVersionedValue, ReadLockedVersionedValue,
-        SharedVersionedValue, StmVar, StmVarId, Version, VersionedValue,
+        self, LabelGuard, LockGuard, LockedVersionedValue,
+        ReadLockedVersionedValue, SharedVersionedValue, StmVar, StmVarId,
+        Version, VersionedValue, Waiter,
     },
     Error, Result,
 };
 use std::{
     any::{self, Any},
     borrow::{Borrow, Cow},
+    cell::{Ref, RefCell, RefMut},
     collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
     fmt,
+    marker::PhantomData,
     ops::Bound,
+    rc::Rc,
+    sync::Arc,
 };
 
-type SharedVersionedMap<K, V> = SharedVersionedValue<BTreeMap<K, V>>;
+type SharedVersionedMap<K, V> = SharedVersionedValue<MapEntries<K, V>>;
+
+/// Committed entries of a map.
+///
+/// Conflicts are detected per key, so transactions that use different keys
+/// of the same map don't conflict with each other.
+struct MapEntries<K, V> {
+    /// Version of the last commit that inserted or removed keys
+    keys_version: Version,
+    entries: BTreeMap<K, VersionedEntry<V>>,
+}
+
+/// Value stamped with the version of the commit that wrote it
+struct VersionedEntry<V> {
+    version: Version,
+    value: V,
+}
+
+impl<K, V> FromIterator<(K, V)> for MapEntries<K, V>
+where
+    K: Ord,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            keys_version: Version::new(),
+            entries: iter
+                .into_iter()
+                .map(|(key, value)| {
+                    let version = Version::new();
+                    (key, VersionedEntry { version, value })
+                })
+                .collect(),
+        }
+    }
+}
 
 /// Atomic map sorted by key
 #[derive(Clone)]
 pub struct StmMap<K, V> {
     var_id: StmVarId,
+    /// Keeps the label registered while the variable is alive
+    _label: Option<Arc<LabelGuard>>,
     map: SharedVersionedMap<K, V>,
 }
 
@@ -27,7 +69,49 @@ impl<K, V> StmMap<K, V> {
     pub fn new() -> Self {
         Self {
             var_id: StmVarId::new(),
-            map: VersionedValue::new_in_shared_lock(BTreeMap::new()),
+            _label: None,
+            map: VersionedValue::new_in_shared_lock(MapEntries {
+                keys_version: Version::new(),
+                entries: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Creates an empty map with a human-readable label,
+    /// see [`StmCell::with_label`](crate::StmCell::with_label)
+    pub fn with_label(label: impl Into<Arc<str>>) -> Self {
+        let (var_id, label) = StmVarId::with_label(label);
+        Self {
+            var_id,
+            _label: Some(label),
+            map: VersionedValue::new_in_shared_lock(MapEntries {
+                keys_version: Version::new(),
+                entries: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn var_id(&self) -> StmVarId {
+        self.var_id
+    }
+}
+
+impl<K, V> StmMap<K, V>
+where
+    K: Ord,
+{
+    /// Creates a map of the entries with a human-readable label
+    pub fn from_iter_with_label<I>(label: impl Into<Arc<str>>, iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let (var_id, label) = StmVarId::with_label(label);
+        Self {
+            var_id,
+            _label: Some(label),
+            map: VersionedValue::new_in_shared_lock(MapEntries::from_iter(
+                iter,
+            )),
         }
     }
 }
@@ -45,14 +129,17 @@ where
     fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
         Self {
             var_id: StmVarId::new(),
-            map: VersionedValue::new_in_shared_lock(BTreeMap::from_iter(iter)),
+            _label: None,
+            map: VersionedValue::new_in_shared_lock(MapEntries::from_iter(
+                iter,
+            )),
         }
     }
 }
 
 impl<K, V> StmVar for StmMap<K, V>
 where
-    K: Ord + 'static,
+    K: Ord + Clone + 'static,
     V: Clone + 'static,
 {
     type TxVar = TxMap<K, V>;
@@ -61,14 +148,22 @@ where
         self.var_id
     }
 
-    fn tx_var(&self) -> Self::TxVar {
-        let initial_version = self.map.read().version.clone();
-        TxMap {
-            initial_version,
+    fn tx_var(
+        &self,
+        read_version: &Version,
+    ) -> Result<Self::TxVar, Infallible> {
+        // The shared value is validated against `read_version` on every read
+        Ok(TxMap {
+            var_id: self.var_id,
+            read_version: read_version.clone(),
             map: variable::clone_shared_lock(&self.map),
+            read_set: Rc::new(RefCell::new(ReadSet {
+                keys: BTreeSet::new(),
+                key_set: false,
+            })),
             tx_map: BTreeMap::new(),
             tx_removed_keys: BTreeSet::new(),
-        }
+        })
     }
 }
 
@@ -80,17 +175,44 @@ impl<K, V> fmt::Debug for StmMap<K, V> {
     }
 }
 
+/// Parts of a shared map observed by a transaction
+struct ReadSet<K> {
+    keys: BTreeSet<K>,
+    /// The transaction has observed the set of keys, e.g. it found out
+    /// that a key is absent or iterated over the map
+    key_set: bool,
+}
+
+impl<K> ReadSet<K> {
+    fn read_key_set<V, E>(
+        &mut self,
+        var_id: StmVarId,
+        map: &MapEntries<K, V>,
+        read_version: &Version,
+    ) -> Result<(), E> {
+        if &map.keys_version > read_version {
+            return Err(Error::ConcurrentUpdate(var_id));
+        }
+        self.key_set = true;
+        Ok(())
+    }
+}
+
 /// A handle for [`StmMap`] tracked by a transaction
 pub struct TxMap<K, V> {
-    initial_version: Version,
+    var_id: StmVarId,
+    read_version: Version,
     map: SharedVersionedMap<K, V>,
+    /// Shared with snapshots, because the reads made by a rolled back part
+    /// of the transaction must still be validated at commit
+    read_set: Rc<RefCell<ReadSet<K>>>,
     tx_map: BTreeMap<K, V>,
     tx_removed_keys: BTreeSet<K>,
 }
 
 impl<K, V> TxMap<K, V>
 where
-    K: Ord,
+    K: Ord + Clone,
     V: Clone,
 {
     pub fn insert(&mut self, key: K, value: V) {
@@ -98,7 +220,7 @@ where
         self.tx_map.insert(key, value);
     }
 
-    pub fn get<Q>(&self, key: &Q) -> Result<Option<Cow<V>>>
+    pub fn get<Q, E>(&self, key: &Q) -> Result<Option<Cow<'_, V>>, E>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
@@ -109,12 +231,13 @@ where
         if self.tx_removed_keys.contains(key) {
             return Ok(None);
         }
-        Ok(self.read_map()?.data.get(key).cloned().map(Cow::Owned))
+        let value = self.read_map().get(key)?.map(|(_, value)| value.clone());
+        Ok(value.map(Cow::Owned))
     }
 
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Result<Option<&mut V>>
+    pub fn get_mut<Q, E>(&mut self, key: &Q) -> Result<Option<&mut V>, E>
     where
-        K: Borrow<Q> + Clone,
+        K: Borrow<Q>,
         Q: Ord + ?Sized,
     {
         if self.tx_map.contains_key(key) {
@@ -123,9 +246,8 @@ where
         if self.tx_removed_keys.contains(key) {
             return Ok(None);
         }
-        let map = self.read_map()?;
-        let key_value = map.data.get_key_value(key);
-        if let Some((key, value)) = key_value {
+        let mut map = self.read_map();
+        if let Some((key, value)) = map.get(key)? {
             let (key, value) = (key.clone(), value.clone());
             drop(map);
             return Ok(Some(self.tx_map.entry(key).or_insert(value)));
@@ -133,7 +255,7 @@ where
         Ok(None)
     }
 
-    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    pub fn contains_key<Q, E>(&self, key: &Q) -> Result<bool, E>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
@@ -144,23 +266,19 @@ where
         if self.tx_removed_keys.contains(key) {
             return Ok(false);
         }
-        Ok(self.read_map()?.data.contains_key(key))
+        Ok(self.read_map().get(key)?.is_some())
     }
 
     /// Returns the minimum key in the map. If result is `None`, then the map is empty.
-    pub fn first_key(&self) -> Result<Option<Cow<K>>>
-    where
-        K: Clone,
-    {
+    pub fn first_key<E>(&self) -> Result<Option<Cow<'_, K>>, E> {
         let Self {
             tx_map,
             tx_removed_keys,
             ..
         } = self;
-        let map = self.read_map()?;
+        let mut map = self.read_map();
         let map_min_key = map
-            .data
-            .keys()
+            .keys()?
             .find(|key| !tx_removed_keys.contains(key))
             .cloned()
             .map(Cow::<'_, K>::Owned);
@@ -181,19 +299,95 @@ where
         self.tx_removed_keys.insert(key);
     }
 
-    pub fn iter(&self) -> Iter<'_, K, V>
+    /// Iterates over the entries in the ascending order of their keys
+    pub fn iter<E>(&self) -> Iter<'_, K, V, E> {
+        Iter {
+            map: self,
+            cursor: Bound::Unbounded,
+            _error: PhantomData,
+        }
+    }
+
+    fn read_map(&self) -> MapSnapshot<'_, K, V> {
+        MapSnapshot {
+            var_id: self.var_id,
+            map: self.map.read(),
+            read_version: &self.read_version,
+            read_set: self.read_set.borrow_mut(),
+        }
+    }
+}
+
+/// Committed entries of a map as they were at the read version of a transaction.
+/// All the reads are recorded in the read set of the transaction.
+struct MapSnapshot<'a, K, V> {
+    var_id: StmVarId,
+    map: ReadLockedVersionedValue<'a, MapEntries<K, V>>,
+    read_version: &'a Version,
+    read_set: RefMut<'a, ReadSet<K>>,
+}
+
+impl<'a, K, V> MapSnapshot<'a, K, V>
+where
+    K: Ord + Clone,
+{
+    fn get<Q, E>(&mut self, key: &Q) -> Result<Option<(&K, &V)>, E>
     where
-        K: Clone,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
     {
-        self.into_iter()
+        let Self {
+            var_id,
+            map,
+            read_version,
+            read_set,
+        } = self;
+        let Some((key, entry)) = map.data.entries.get_key_value(key) else {
+            read_set.read_key_set(*var_id, &map.data, read_version)?;
+            return Ok(None);
+        };
+        if &entry.version > read_version {
+            return Err(Error::ConcurrentUpdate(*var_id));
+        }
+        if !read_set.keys.contains::<K>(key) {
+            read_set.keys.insert(key.clone());
+        }
+        Ok(Some((key, &entry.value)))
     }
 
-    fn read_map(&self) -> Result<ReadLockedVersionedValue<'_, BTreeMap<K, V>>> {
-        let map = self.map.read();
-        if self.initial_version != map.version {
-            return Err(Error::ConcurrentUpdate);
+    fn keys<E>(&mut self) -> Result<impl Iterator<Item = &K>, E> {
+        self.read_set.read_key_set(
+            self.var_id,
+            &self.map.data,
+            self.read_version,
+        )?;
+        Ok(self.map.data.entries.keys())
+    }
+
+    fn range<E>(
+        &mut self,
+        range: (Bound<&K>, Bound<&K>),
+        tx_removed_keys: &BTreeSet<K>,
+    ) -> Result<Option<(&K, &V)>, E> {
+        self.read_set.read_key_set(
+            self.var_id,
+            &self.map.data,
+            self.read_version,
+        )?;
+        let key = self
+            .map
+            .data
+            .entries
+            .range::<K, _>(range)
+            .map(|(key, _)| key)
+            .find(|key| !tx_removed_keys.contains(key));
+        match key {
+            Some(key) => {
+                let key = key.clone();
+                self.get(&key)
+            }
+            None => Ok(None),
         }
-        Ok(map)
     }
 }
 
@@ -207,37 +401,61 @@ impl<K, V> fmt::Debug for TxMap<K, V> {
 
 impl<K, V> TxVar for TxMap<K, V>
 where
-    K: Ord + 'static,
-    V: 'static,
+    K: Ord + Clone + 'static,
+    V: Clone + 'static,
 {
+    fn has_changes(&self) -> bool {
+        !self.tx_map.is_empty() || !self.tx_removed_keys.is_empty()
+    }
+
     fn lock(&mut self) -> Box<dyn LockedTxVar + '_> {
+        let has_changes = self.has_changes();
         let Self {
-            initial_version,
+            read_version,
             map,
+            read_set,
             tx_map,
             tx_removed_keys,
+            ..
         } = self;
-        let map = if tx_map.is_empty() && tx_removed_keys.is_empty() {
-            LockGuard::Read(map.read())
-        } else {
+        let map = if has_changes {
             LockGuard::Write(map.write())
+        } else {
+            LockGuard::Read(map.read())
         };
         Box::new(LockedTxMap {
-            initial_version: initial_version.clone(),
+            read_version: read_version.clone(),
             map,
+            read_set: RefCell::borrow(read_set),
             tx_map,
             tx_removed_keys,
         })
     }
 
+    fn subscribe(&self, waiter: &Arc<Waiter>) -> bool {
+        self.map.write().subscribe(&self.read_version, waiter)
+    }
+
+    fn snapshot(&self) -> Box<dyn TxVar> {
+        Box::new(Self {
+            var_id: self.var_id,
+            read_version: self.read_version.clone(),
+            map: variable::clone_shared_lock(&self.map),
+            read_set: Rc::clone(&self.read_set),
+            tx_map: self.tx_map.clone(),
+            tx_removed_keys: self.tx_removed_keys.clone(),
+        })
+    }
+
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
 }
 
 struct LockedTxMap<'a, K, V> {
-    initial_version: Version,
-    map: LockedVersionedValue<'a, BTreeMap<K, V>>,
+    read_version: Version,
+    map: LockedVersionedValue<'a, MapEntries<K, V>>,
+    read_set: Ref<'a, ReadSet<K>>,
     tx_map: &'a mut BTreeMap<K, V>,
     tx_removed_keys: &'a mut BTreeSet<K>,
 }
@@ -247,19 +465,39 @@ where
     K: Ord,
 {
     fn can_commit(&self) -> bool {
-        &self.initial_version == self.map.current_version()
+        let read_version = &self.read_version;
+        if self.map.current_version() <= read_version {
+            return true;
+        }
+        let map = self.map.data();
+        if self.read_set.key_set && &map.keys_version > read_version {
+            return false;
+        }
+        self.read_set.keys.iter().all(|key| {
+            map.entries
+                .get(key)
+                .is_some_and(|entry| &entry.version <= read_version)
+        })
     }
 
-    fn commit(&mut self) {
+    fn commit(&mut self, write_version: &Version) {
         let map = match &mut self.map {
             LockGuard::Read(_) => return,
             LockGuard::Write(map) => map,
         };
-        map.version.increment();
-        for k in self.tx_removed_keys.iter() {
-            map.data.remove(k);
+        let mut keys_changed = false;
+        for key in self.tx_removed_keys.iter() {
+            keys_changed |= map.data.entries.remove(key).is_some();
+        }
+        for (key, value) in std::mem::take(self.tx_map) {
+            let version = write_version.clone();
+            let entry = VersionedEntry { version, value };
+            keys_changed |= map.data.entries.insert(key, entry).is_none();
         }
-        map.data.append(self.tx_map)
+        if keys_changed {
+            map.data.keys_version = write_version.clone();
+        }
+        map.update_version(write_version);
     }
 }
 
@@ -272,24 +510,22 @@ where
     type Item = <Self::IntoIter as Iterator>::Item;
 
     fn into_iter(self) -> Self::IntoIter {
-        Iter {
-            map: self,
-            cursor: Bound::Unbounded,
-        }
+        self.iter()
     }
 }
 
-pub struct Iter<'a, K, V> {
+pub struct Iter<'a, K, V, E = ()> {
     map: &'a TxMap<K, V>,
     cursor: Bound<K>,
+    _error: PhantomData<fn() -> E>,
 }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V>
+impl<'a, K, V, E> Iterator for Iter<'a, K, V, E>
 where
     K: Ord + Clone,
     V: Clone,
 {
-    type Item = Result<(K, V)>;
+    type Item = Result<(K, V), E>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let Self {
@@ -300,34 +536,29 @@ where
                     ..
                 },
             cursor,
+            ..
         } = self;
         let range = (cursor.as_ref(), Bound::Unbounded.as_ref());
-        let map = match self.map.read_map() {
-            Ok(map) => map,
+        let mut map = self.map.read_map();
+        let map_min_key_val = match map.range(range, tx_removed_keys) {
+            Ok(key_val) => key_val,
             Err(err) => return Some(Err(err)),
         };
-        let map_min_key_val = map
-            .data
-            .range(range)
-            .find(|key_val| !tx_removed_keys.contains(key_val.0));
         let tx_map_min_key_val = tx_map.range(range).next();
         let min_key_val = match (map_min_key_val, tx_map_min_key_val) {
             (Some(map_min_key_val), Some(tx_map_min_key_val)) => {
                 Some(if map_min_key_val.0 < tx_map_min_key_val.0 {
                     map_min_key_val
                 } else {
-                    drop(map);
                     tx_map_min_key_val
                 })
             }
             (Some(map_min_key_val), None) => Some(map_min_key_val),
-            (None, Some(tx_map_min_key_val)) => {
-                drop(map);
-                Some(tx_map_min_key_val)
-            }
+            (None, Some(tx_map_min_key_val)) => Some(tx_map_min_key_val),
             (None, None) => None,
         }
         .map(owned_key_value);
+        drop(map);
         if let Some(ref min_key_val) = min_key_val {
             *cursor = Bound::Excluded(min_key_val.0.clone())
         }
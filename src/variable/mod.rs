@@ -2,29 +2,108 @@ pub mod cell;
 pub mod map;
 pub mod queue;
 
-use crate::transaction::TxVar;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::{
+    timer::{self, Sleep},
+    transaction::TxVar,
+    Error, Result,
+};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Unique identifier of an STM variable.
+///
+/// If the variable has been created with a label, e.g. by
+/// [`StmCell::with_label`](cell::StmCell::with_label), the label is shown
+/// by the `Display` and `Debug` implementations.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StmVarId(usize);
 
+/// Labels of the alive STM variables
+static LABELS: parking_lot::RwLock<BTreeMap<StmVarId, Arc<str>>> =
+    parking_lot::const_rwlock(BTreeMap::new());
+
 impl StmVarId {
-    fn new() -> Self {
+    /// Creates a new unique ID, e.g. for a custom [`StmVar`]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
         static CURRENT_ID: AtomicUsize = AtomicUsize::new(0);
         Self(CURRENT_ID.fetch_add(1, Ordering::SeqCst))
     }
+
+    /// Creates an ID with the label, which is kept
+    /// until the returned guard is dropped
+    fn with_label(label: impl Into<Arc<str>>) -> (Self, Arc<LabelGuard>) {
+        let var_id = Self::new();
+        LABELS.write().insert(var_id, label.into());
+        (var_id, Arc::new(LabelGuard(var_id)))
+    }
+
+    /// The label of the variable, if it has one and it's still alive
+    pub fn label(&self) -> Option<Arc<str>> {
+        LABELS.read().get(self).cloned()
+    }
+}
+
+impl fmt::Debug for StmVarId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label() {
+            Some(label) => write!(f, "StmVarId({}, {label:?})", self.0),
+            None => write!(f, "StmVarId({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for StmVarId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label() {
+            Some(label) => write!(f, "{label}"),
+            None => write!(f, "#{}", self.0),
+        }
+    }
+}
+
+/// Removes the label of an STM variable when the last clone
+/// of the variable is dropped
+struct LabelGuard(StmVarId);
+
+impl Drop for LabelGuard {
+    fn drop(&mut self) {
+        LABELS.write().remove(&self.0);
+    }
 }
 
-/// A variable to be shared across multiple transactions.
+/// A variable to be shared across multiple transactions, e.g. [`StmCell`](crate::StmCell).
 ///
-/// The trait is private since it's not re-exported in the root module.
+/// The trait can be implemented for custom transactional containers.
+/// Their shared state is usually kept in a [`VersionedValue`] behind a lock.
+/// With the `conformance` feature, the `conformance` module checks
+/// that an implementation behaves like the built-in containers.
 pub trait StmVar {
+    /// In-transaction state of the variable, which is accessed through
+    /// the handle returned by [`Tx::track`](crate::Tx::track)
     type TxVar: TxVar;
 
+    /// Must be created by [`StmVarId::new`] once for the variable,
+    /// and be shared by its clones
     fn var_id(&self) -> StmVarId;
 
-    /// Implementation must remember the original version of a variable at this point
-    fn tx_var(&self) -> Self::TxVar;
+    /// Reads the variable as of `read_version`, i.e. as it was when the transaction started.
+    ///
+    /// Implementation must return [`Error::ConcurrentUpdate`](crate::Error::ConcurrentUpdate)
+    /// if the variable has been changed after `read_version`.
+    fn tx_var(&self, read_version: &Version)
+        -> Result<Self::TxVar, Infallible>;
 }
 
 impl<T> StmVar for &T
@@ -37,35 +116,192 @@ where
         T::var_id(self)
     }
 
-    fn tx_var(&self) -> Self::TxVar {
-        T::tx_var(self)
+    fn tx_var(
+        &self,
+        read_version: &Version,
+    ) -> Result<Self::TxVar, Infallible> {
+        T::tx_var(self, read_version)
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
-struct Version(usize);
+/// Timestamp of the global clock that is advanced by every commit of a transaction
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(usize);
+
+static GLOBAL_CLOCK: AtomicUsize = AtomicUsize::new(0);
 
 impl Version {
     fn new() -> Self {
         Self(0)
     }
 
-    fn increment(&mut self) {
-        self.0 += 1
+    /// The version that allows a transaction to read all values committed so far
+    pub(crate) fn read() -> Self {
+        Self(GLOBAL_CLOCK.load(Ordering::SeqCst))
+    }
+
+    /// The version that will be assigned to values written by a committing transaction
+    pub(crate) fn write() -> Self {
+        Self(GLOBAL_CLOCK.fetch_add(1, Ordering::SeqCst) + 1)
     }
 }
 
-struct VersionedValue<T> {
+/// Shared state of an STM variable stamped with the version of the last commit
+/// that has changed it. It also keeps the transactions waiting for a change
+/// after [`Tx::retry`](crate::Tx::retry).
+pub struct VersionedValue<T> {
     version: Version,
     data: T,
+    /// Transactions that wait for the value to be changed
+    waiters: Vec<Weak<Waiter>>,
 }
 
 impl<T> VersionedValue<T> {
-    fn new_in_shared_lock(data: T) -> SharedVersionedValue<T> {
-        rclite::Arc::new(parking_lot::RwLock::new(Self {
+    pub fn new(data: T) -> Self {
+        Self {
             version: Version::new(),
             data,
-        }))
+            waiters: Vec::new(),
+        }
+    }
+
+    fn new_in_shared_lock(data: T) -> SharedVersionedValue<T> {
+        rclite::Arc::new(parking_lot::RwLock::new(Self::new(data)))
+    }
+
+    /// The version of the last commit that has changed the value
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// Returns the value if it hasn't been changed after `read_version`,
+    /// otherwise returns [`Error::ConcurrentUpdate`](crate::Error::ConcurrentUpdate)
+    pub fn read<E>(
+        &self,
+        var_id: StmVarId,
+        read_version: &Version,
+    ) -> Result<&T, E> {
+        if &self.version > read_version {
+            return Err(Error::ConcurrentUpdate(var_id));
+        }
+        Ok(&self.data)
+    }
+
+    /// Mutable access for a committing transaction, which must call
+    /// [`update_version`](#method.update_version) afterwards
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    /// Must be called by a committing transaction after it has changed the value.
+    /// Notifies the waiting transactions.
+    pub fn update_version(&mut self, write_version: &Version) {
+        self.version = write_version.clone();
+        for waiter in self.waiters.drain(..) {
+            if let Some(waiter) = waiter.upgrade() {
+                waiter.notify()
+            }
+        }
+    }
+
+    /// Registers the waiter to be notified about the next change of the value.
+    /// Returns `false` if the value has already changed since the `read_version`.
+    pub fn subscribe(
+        &mut self,
+        read_version: &Version,
+        waiter: &Arc<Waiter>,
+    ) -> bool {
+        if &self.version > read_version {
+            return false;
+        }
+        self.waiters.retain(|waiter| waiter.strong_count() > 0);
+        self.waiters.push(Arc::downgrade(waiter));
+        true
+    }
+}
+
+/// Parks a thread or suspends a task until one of the STM variables
+/// it is subscribed to gets changed, see [`VersionedValue::subscribe`]
+pub struct Waiter {
+    state: parking_lot::Mutex<WaiterState>,
+    condvar: parking_lot::Condvar,
+}
+
+struct WaiterState {
+    notified: bool,
+    waker: Option<Waker>,
+}
+
+impl Waiter {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            state: parking_lot::Mutex::new(WaiterState {
+                notified: false,
+                waker: None,
+            }),
+            condvar: parking_lot::Condvar::new(),
+        })
+    }
+
+    /// Parks the thread until the waiter is notified or the deadline has passed
+    pub(crate) fn wait(&self, deadline: Option<Instant>) {
+        let mut state = self.state.lock();
+        while !state.notified {
+            match deadline {
+                Some(deadline) => {
+                    if self.condvar.wait_until(&mut state, deadline).timed_out()
+                    {
+                        return;
+                    }
+                }
+                None => self.condvar.wait(&mut state),
+            }
+        }
+    }
+
+    /// Async version of [`wait`](#method.wait)
+    pub(crate) fn wait_async(
+        &self,
+        deadline: Option<Instant>,
+    ) -> WaitForChange<'_> {
+        WaitForChange {
+            waiter: self,
+            timeout: deadline.map(timer::sleep_until),
+        }
+    }
+
+    pub(crate) fn notify(&self) {
+        let mut state = self.state.lock();
+        state.notified = true;
+        let waker = state.waker.take();
+        drop(state);
+        self.condvar.notify_one();
+        if let Some(waker) = waker {
+            waker.wake()
+        }
+    }
+}
+
+pub struct WaitForChange<'a> {
+    waiter: &'a Waiter,
+    timeout: Option<Sleep>,
+}
+
+impl Future for WaitForChange<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(timeout) = &mut self.timeout {
+            if Pin::new(timeout).poll(cx).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        let mut state = self.waiter.state.lock();
+        if state.notified {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
     }
 }
 
@@ -81,6 +317,13 @@ impl<'a, T> LockGuard<'a, VersionedValue<T>> {
             LockGuard::Write(queue) => &queue.version,
         }
     }
+
+    fn data(&self) -> &T {
+        match &self {
+            LockGuard::Read(value) => &value.data,
+            LockGuard::Write(value) => &value.data,
+        }
+    }
 }
 
 type SharedRwLock<T> = rclite::Arc<parking_lot::RwLock<T>>;
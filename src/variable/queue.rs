@@ -1,34 +1,99 @@
 use crate::{
     transaction::{LockedTxVar, TxVar},
     variable::{
-        self, LockGuard, LockedVersionedValue, ReadLockedVersionedValue,
-        SharedVersionedValue, StmVar, StmVarId, Version, VersionedValue,
+        self, LabelGuard, LockGuard, LockedVersionedValue,
+        ReadLockedVersionedValue, SharedVersionedValue, StmVar, StmVarId,
+        Version, VersionedValue, Waiter,
     },
     Error, Result,
 };
 use std::{
     any::{self, Any},
     borrow::Cow,
+    cell::{Ref, RefCell, RefMut},
     collections::VecDeque,
+    convert::Infallible,
     fmt,
+    marker::PhantomData,
+    rc::Rc,
+    sync::Arc,
 };
 
-type SharedVersionedDeque<T> = SharedVersionedValue<VecDeque<T>>;
+type SharedVersionedDeque<T> = SharedVersionedValue<QueueItems<T>>;
+
+/// Committed items of a queue.
+///
+/// The head and the tail of the queue are versioned separately, so transactions
+/// that only push items don't conflict with each other and with consumers
+/// that don't reach the end of the queue.
+struct QueueItems<T> {
+    /// Version of the last commit that popped items
+    head_version: Version,
+    /// Version of the last commit that pushed items
+    tail_version: Version,
+    items: VecDeque<VersionedItem<T>>,
+}
+
+/// Item stamped with the version of the commit that pushed it
+struct VersionedItem<T> {
+    version: Version,
+    item: T,
+}
+
+impl<T> FromIterator<T> for QueueItems<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            head_version: Version::new(),
+            tail_version: Version::new(),
+            items: iter
+                .into_iter()
+                .map(|item| {
+                    let version = Version::new();
+                    VersionedItem { version, item }
+                })
+                .collect(),
+        }
+    }
+}
 
 /// Atomic queue
 #[derive(Clone)]
 pub struct StmQueue<T> {
     var_id: StmVarId,
+    /// Keeps the label registered while the variable is alive
+    _label: Option<Arc<LabelGuard>>,
     queue: SharedVersionedDeque<T>,
 }
 
 impl<T> StmQueue<T> {
     pub fn new() -> Self {
+        Self::from_iter([])
+    }
+
+    /// Creates an empty queue with a human-readable label,
+    /// see [`StmCell::with_label`](crate::StmCell::with_label)
+    pub fn with_label(label: impl Into<Arc<str>>) -> Self {
+        Self::from_iter_with_label(label, [])
+    }
+
+    /// Creates a queue of the items with a human-readable label
+    pub fn from_iter_with_label<I>(label: impl Into<Arc<str>>, iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let (var_id, label) = StmVarId::with_label(label);
         Self {
-            var_id: StmVarId::new(),
-            queue: VersionedValue::new_in_shared_lock(VecDeque::new()),
+            var_id,
+            _label: Some(label),
+            queue: VersionedValue::new_in_shared_lock(QueueItems::from_iter(
+                iter,
+            )),
         }
     }
+
+    pub fn var_id(&self) -> StmVarId {
+        self.var_id
+    }
 }
 
 impl<T> Default for StmQueue<T> {
@@ -41,7 +106,8 @@ impl<T> FromIterator<T> for StmQueue<T> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         Self {
             var_id: StmVarId::new(),
-            queue: VersionedValue::new_in_shared_lock(VecDeque::from_iter(
+            _label: None,
+            queue: VersionedValue::new_in_shared_lock(QueueItems::from_iter(
                 iter,
             )),
         }
@@ -58,14 +124,22 @@ where
         self.var_id
     }
 
-    fn tx_var(&self) -> Self::TxVar {
-        let initial_version = self.queue.read().version.clone();
-        TxQueue {
-            initial_version,
+    fn tx_var(
+        &self,
+        read_version: &Version,
+    ) -> Result<Self::TxVar, Infallible> {
+        // The shared value is validated against `read_version` on every read
+        Ok(TxQueue {
+            var_id: self.var_id,
+            read_version: read_version.clone(),
             queue: variable::clone_shared_lock(&self.queue),
+            read_set: Rc::new(RefCell::new(ReadSet {
+                head: false,
+                tail: false,
+            })),
             front_position: 0,
             push_back_items: VecDeque::new(),
-        }
+        })
     }
 }
 
@@ -75,10 +149,22 @@ impl<T> fmt::Debug for StmQueue<T> {
     }
 }
 
+/// Parts of a shared queue observed by a transaction
+struct ReadSet {
+    /// The transaction has read committed items
+    head: bool,
+    /// The transaction has reached the end of the committed items
+    tail: bool,
+}
+
 /// A handle for [`StmQueue`] tracked by a transaction
 pub struct TxQueue<T> {
-    initial_version: Version,
+    var_id: StmVarId,
+    read_version: Version,
     queue: SharedVersionedDeque<T>,
+    /// Shared with snapshots, because the reads made by a rolled back part
+    /// of the transaction must still be validated at commit
+    read_set: Rc<RefCell<ReadSet>>,
     front_position: usize,
     push_back_items: VecDeque<T>,
 }
@@ -93,10 +179,8 @@ where
     }
 
     /// Dequeue an element
-    pub fn pop(&mut self) -> Result<Option<T>> {
-        let queue = self.read_queue()?;
-        let item = queue.data.get(self.front_position).cloned();
-        drop(queue);
+    pub fn pop<E>(&mut self) -> Result<Option<T>, E> {
+        let item = self.read_queue().get(self.front_position)?.cloned();
         if item.is_some() {
             self.front_position += 1;
         }
@@ -104,30 +188,80 @@ where
     }
 
     /// Get the next element to be dequeued without consuming it
-    pub fn peek(&self) -> Result<Option<Cow<T>>> {
-        let queue = self.read_queue()?;
-        let item = queue.data.get(self.front_position).cloned().map(Cow::Owned);
+    pub fn peek<E>(&self) -> Result<Option<Cow<'_, T>>, E> {
+        let mut queue = self.read_queue();
+        let item = queue.get(self.front_position)?.cloned().map(Cow::Owned);
         drop(queue);
         Ok(item.or_else(|| self.push_back_items.front().map(Cow::Borrowed)))
     }
 
-    pub fn is_empty(&self) -> Result<bool> {
-        if self.front_position < self.read_queue()?.data.len() {
+    pub fn is_empty<E>(&self) -> Result<bool, E> {
+        if self.read_queue().get(self.front_position)?.is_some() {
             return Ok(false);
         }
         Ok(self.push_back_items.is_empty())
     }
 
-    pub fn iter(&self) -> Iter<'_, T> {
-        self.into_iter()
+    /// Iterates over the elements in the order they would be dequeued
+    pub fn iter<E>(&self) -> Iter<'_, T, E> {
+        Iter {
+            queue: self,
+            cursor: 0,
+            _error: PhantomData,
+        }
     }
 
-    fn read_queue(&self) -> Result<ReadLockedVersionedValue<'_, VecDeque<T>>> {
-        let queue = self.queue.read();
-        if self.initial_version != queue.version {
-            return Err(Error::ConcurrentUpdate);
+    fn read_queue(&self) -> QueueSnapshot<'_, T> {
+        QueueSnapshot {
+            var_id: self.var_id,
+            queue: self.queue.read(),
+            read_version: &self.read_version,
+            read_set: self.read_set.borrow_mut(),
         }
-        Ok(queue)
+    }
+}
+
+/// Committed items of a queue as they were at the read version of a transaction.
+/// All the reads are recorded in the read set of the transaction.
+struct QueueSnapshot<'a, T> {
+    var_id: StmVarId,
+    queue: ReadLockedVersionedValue<'a, QueueItems<T>>,
+    read_version: &'a Version,
+    read_set: RefMut<'a, ReadSet>,
+}
+
+impl<'a, T> QueueSnapshot<'a, T> {
+    /// Returns `None` if the position is beyond the end of the committed items.
+    /// In this case, all the committed items are visible to the transaction.
+    fn get<E>(&mut self, position: usize) -> Result<Option<&T>, E> {
+        let Self {
+            var_id,
+            queue,
+            read_version,
+            read_set,
+        } = self;
+        if &queue.data.head_version > *read_version {
+            return Err(Error::ConcurrentUpdate(*var_id));
+        }
+        read_set.head = true;
+        match queue.data.items.get(position) {
+            Some(versioned) if &versioned.version <= *read_version => {
+                Ok(Some(&versioned.item))
+            }
+            // The item has been pushed after the transaction started,
+            // so the transaction has reached the end of the queue
+            _ => {
+                if &queue.data.tail_version > *read_version {
+                    return Err(Error::ConcurrentUpdate(*var_id));
+                }
+                read_set.tail = true;
+                Ok(None)
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.queue.data.items.len()
     }
 }
 
@@ -137,54 +271,92 @@ impl<T> fmt::Debug for TxQueue<T> {
     }
 }
 
-impl<T: 'static> TxVar for TxQueue<T> {
+impl<T: Clone + 'static> TxVar for TxQueue<T> {
+    fn has_changes(&self) -> bool {
+        self.front_position > 0 || !self.push_back_items.is_empty()
+    }
+
     fn lock(&mut self) -> Box<dyn LockedTxVar + '_> {
+        let has_changes = self.has_changes();
         let Self {
-            initial_version,
+            read_version,
             queue,
+            read_set,
             front_position,
             push_back_items,
+            ..
         } = self;
-        let queue = if *front_position > 0 || !push_back_items.is_empty() {
+        let queue = if has_changes {
             LockGuard::Write(queue.write())
         } else {
             LockGuard::Read(queue.read())
         };
         Box::new(LockedTxQueue {
-            initial_version: initial_version.clone(),
+            read_version: read_version.clone(),
             queue,
+            read_set: RefCell::borrow(read_set),
             front_position: *front_position,
             push_back_items,
         })
     }
 
+    fn subscribe(&self, waiter: &Arc<Waiter>) -> bool {
+        self.queue.write().subscribe(&self.read_version, waiter)
+    }
+
+    fn snapshot(&self) -> Box<dyn TxVar> {
+        Box::new(Self {
+            var_id: self.var_id,
+            read_version: self.read_version.clone(),
+            queue: variable::clone_shared_lock(&self.queue),
+            read_set: Rc::clone(&self.read_set),
+            front_position: self.front_position,
+            push_back_items: self.push_back_items.clone(),
+        })
+    }
+
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
 }
 
 struct LockedTxQueue<'a, T> {
-    initial_version: Version,
-    queue: LockedVersionedValue<'a, VecDeque<T>>,
+    read_version: Version,
+    queue: LockedVersionedValue<'a, QueueItems<T>>,
+    read_set: Ref<'a, ReadSet>,
     front_position: usize,
     push_back_items: &'a mut VecDeque<T>,
 }
 
 impl<'a, T> LockedTxVar for LockedTxQueue<'a, T> {
     fn can_commit(&self) -> bool {
-        &self.initial_version == self.queue.current_version()
+        let read_version = &self.read_version;
+        if self.queue.current_version() <= read_version {
+            return true;
+        }
+        let queue = self.queue.data();
+        (!self.read_set.head || &queue.head_version <= read_version)
+            && (!self.read_set.tail || &queue.tail_version <= read_version)
     }
 
-    fn commit(&mut self) {
+    fn commit(&mut self, write_version: &Version) {
         let queue = match &mut self.queue {
             LockGuard::Read(_) => return,
             LockGuard::Write(queue) => queue,
         };
-        queue.version.increment();
-        for _ in 0..self.front_position {
-            queue.data.pop_front();
+        if self.front_position > 0 {
+            queue.data.items.drain(..self.front_position);
+            queue.data.head_version = write_version.clone();
+        }
+        if !self.push_back_items.is_empty() {
+            let items = self.push_back_items.drain(..).map(|item| {
+                let version = write_version.clone();
+                VersionedItem { version, item }
+            });
+            queue.data.items.extend(items);
+            queue.data.tail_version = write_version.clone();
         }
-        queue.data.append(self.push_back_items)
+        queue.update_version(write_version);
     }
 }
 
@@ -196,23 +368,21 @@ where
     type Item = <Self::IntoIter as Iterator>::Item;
 
     fn into_iter(self) -> Self::IntoIter {
-        Iter {
-            queue: self,
-            cursor: 0,
-        }
+        self.iter()
     }
 }
 
-pub struct Iter<'a, T> {
+pub struct Iter<'a, T, E = ()> {
     queue: &'a TxQueue<T>,
     cursor: usize,
+    _error: PhantomData<fn() -> E>,
 }
 
-impl<'a, T> Iterator for Iter<'a, T>
+impl<'a, T, E> Iterator for Iter<'a, T, E>
 where
     T: Clone,
 {
-    type Item = Result<Cow<'a, T>>;
+    type Item = Result<Cow<'a, T>, E>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let Self {
@@ -223,20 +393,19 @@ where
                     ..
                 },
             cursor,
+            ..
         } = self;
-        let mut position = *cursor + *front_position;
-        let queue = match self.queue.read_queue() {
-            Ok(queue) => queue,
+        let position = *cursor + *front_position;
+        let mut queue = self.queue.read_queue();
+        let item = match queue.get(position) {
+            Ok(item) => item.cloned().map(Cow::Owned),
             Err(err) => return Some(Err(err)),
         };
-        let queue_len = queue.data.len();
-        let item = queue.data.get(position).cloned().map(Cow::Owned);
+        let queue_len = queue.len();
         drop(queue);
-        if position >= queue_len {
-            position -= queue_len;
-        }
-        let item =
-            item.or_else(|| push_back_items.get(position).map(Cow::Borrowed));
+        let item = item.or_else(|| {
+            push_back_items.get(position - queue_len).map(Cow::Borrowed)
+        });
         if item.is_some() {
             *cursor += 1;
         }
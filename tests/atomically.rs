@@ -0,0 +1,59 @@
+use assert_matches::assert_matches;
+use naive_stm::{atomically, Error, StmCell, StmMap, StmQueue, Tx, TxOptions};
+use std::thread;
+
+struct Bank {
+    accounts: StmMap<&'static str, u64>,
+    log: StmQueue<String>,
+}
+
+#[test]
+fn atomically() {
+    let bank = Bank {
+        accounts: StmMap::from_iter([("alice", 100), ("bob", 0)]),
+        log: StmQueue::new(),
+    };
+    let fee = &StmCell::new(1);
+
+    let balance = atomically!(bank.accounts, bank.log, &fee as f => {
+        let amount = 30;
+        *accounts.get_mut("alice")?.unwrap() -= amount + **f;
+        *accounts.get_mut("bob")?.unwrap() += amount;
+        log.push(format!("alice -> bob: {amount}"));
+        accounts.get("alice")?.unwrap().into_owned()
+    });
+    assert_eq!(balance.unwrap(), 69);
+
+    let log = atomically!(bank.log => log.pop()?);
+    assert_eq!(log.unwrap().as_deref(), Some("alice -> bob: 30"));
+}
+
+#[test]
+fn options() {
+    let cell = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 1,
+        ..Default::default()
+    };
+    let result = atomically!(options; &cell as c => {
+        if **c == 0 {
+            // Another transaction changes the cell
+            thread::scope(|scope| {
+                scope.spawn(|| atomically!(cell => **cell += 1).unwrap());
+            });
+        }
+        **c += 10;
+    });
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 1, .. })
+    );
+
+    let result = atomically!(TxOptions::default(); &cell as c => {
+        if **c == 1 {
+            Tx::abort()?;
+        }
+    });
+    assert_matches!(result, Err(Error::TransactionAbort(())));
+    assert_eq!(atomically!(cell => **cell).unwrap(), 1);
+}
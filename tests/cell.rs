@@ -1,13 +1,15 @@
 use assert_matches::assert_matches;
-use naive_stm::{track, Error, StmCell, Tx};
-use std::thread;
+use naive_stm::{
+    track, Error, Result, StmCell, StmMap, StmQueue, Tx, TxOptions,
+};
+use std::{sync::Barrier, thread};
 
 fn sleep() {
     thread::sleep(std::time::Duration::from_micros(50))
 }
 
 fn read_cell<T: Clone + 'static>(cell: &StmCell<T>) -> T {
-    Tx::run(|tx| Ok(tx.track(cell)?.clone())).unwrap()
+    Tx::run(|tx| -> Result<T> { Ok(tx.track(cell)?.clone()) }).unwrap()
 }
 
 fn two_transactions_add_2_cells() {
@@ -19,7 +21,7 @@ fn two_transactions_add_2_cells() {
 
     let (tx_1_val, tx_2_val) = thread::scope(|scope| {
         let tx_1 = scope.spawn(|| {
-            Tx::run(|tx| {
+            Tx::run(|tx| -> Result<_> {
                 tx_1_attempts += 1;
 
                 sleep();
@@ -28,7 +30,7 @@ fn two_transactions_add_2_cells() {
                 let b = tx.track(&cell_b)?;
 
                 assert_matches!(
-                    tx.track(&cell_a),
+                    tx.track::<_, ()>(&cell_a),
                     Err(Error::TransactionVariableIsInUse(_))
                 );
 
@@ -41,7 +43,7 @@ fn two_transactions_add_2_cells() {
         });
 
         let tx_2 = scope.spawn(|| {
-            Tx::run(|tx| {
+            Tx::run(|tx| -> Result<_> {
                 tx_2_attempts += 1;
 
                 sleep();
@@ -94,7 +96,7 @@ fn triple_swap() {
         let txs: Vec<_> = (0..3)
             .map(|_| {
                 scope.spawn(|| {
-                    Tx::run(|tx| {
+                    Tx::run(|tx| -> Result {
                         track!(tx, cell_a, cell_b);
                         let ref_a: &mut &str = &mut cell_a;
                         let ref_b: &mut &str = &mut cell_b;
@@ -116,3 +118,266 @@ fn triple_swap() {
     assert_eq!("bar", val_a);
     assert_eq!("foo", val_b);
 }
+
+#[test]
+fn consistent_snapshot() {
+    let cell_a = StmCell::new(50);
+    let cell_b = StmCell::new(50);
+    let transfers = 300;
+
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            for i in 0..transfers {
+                Tx::run(|tx| -> Result {
+                    track!(tx, cell_a, cell_b);
+                    let amount = if i % 2 == 0 { 7 } else { -7 };
+                    **cell_a -= amount;
+                    **cell_b += amount;
+                    Ok(())
+                })
+                .unwrap();
+                sleep();
+            }
+        });
+
+        while !writer.is_finished() {
+            let result = Tx::run(|tx| -> Result {
+                let a = tx.track(&cell_a)?;
+                sleep();
+                let b = tx.track(&cell_b)?;
+                // A zombie attempt would observe the broken invariant here
+                assert_eq!(**a + **b, 100);
+                Ok(())
+            });
+            assert_matches!(
+                result,
+                Ok(()) | Err(Error::TooManyTransactionRetryAttempts { .. })
+            );
+        }
+    });
+}
+
+#[test]
+fn commit_versions_follow_serialization_order() {
+    let counter = StmCell::new(0);
+    let tx_opts = TxOptions {
+        attempts: 1000,
+        ..Default::default()
+    };
+
+    let mut commits: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    (0..20)
+                        .map(|_| {
+                            Tx::run_with_commit_version(
+                                &tx_opts,
+                                |tx| -> Result<_> {
+                                    track!(tx, counter);
+                                    **counter += 1;
+                                    Ok(**counter)
+                                },
+                            )
+                            .unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    commits.sort_by(|(_, version_a), (_, version_b)| version_a.cmp(version_b));
+    let counter_values: Vec<_> =
+        commits.into_iter().map(|(val, _)| val).collect();
+    assert_eq!(counter_values, (1..=160).collect::<Vec<_>>());
+}
+
+#[test]
+fn read_only_transaction_is_not_invalidated() {
+    let cell = StmCell::new(10);
+    let barrier = Barrier::new(2);
+    let mut reader_attempts = 0;
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            Tx::run(|tx| -> Result<_> {
+                reader_attempts += 1;
+                let val = **tx.track(&cell)?;
+                if reader_attempts == 1 {
+                    barrier.wait();
+                    // The writer commits here
+                    barrier.wait();
+                }
+                Ok(val)
+            })
+            .unwrap()
+        });
+
+        barrier.wait();
+        Tx::run(|tx| -> Result {
+            track!(tx, cell);
+            **cell += 1;
+            Ok(())
+        })
+        .unwrap();
+        barrier.wait();
+
+        // The reader is serialized before the writer
+        assert_eq!(reader.join().unwrap(), 10);
+    });
+
+    assert_eq!(reader_attempts, 1);
+    assert_eq!(read_cell(&cell), 11);
+}
+
+#[test]
+fn commuted_counter_never_conflicts() {
+    let requests = StmCell::new(0);
+    let barrier = Barrier::new(2);
+    let mut attempts = 0;
+
+    thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            Tx::run(|tx| -> Result {
+                attempts += 1;
+                tx.commute(&requests, |n| *n += 1)?;
+                if attempts == 1 {
+                    barrier.wait();
+                    // Another transaction increments the counter here
+                    barrier.wait();
+                }
+                tx.commute(&requests, |n| *n += 10)?;
+                Ok(())
+            })
+            .unwrap()
+        });
+
+        barrier.wait();
+        Tx::run(|tx| -> Result {
+            track!(tx, requests);
+            **requests += 100;
+            Ok(())
+        })
+        .unwrap();
+        barrier.wait();
+        worker.join().unwrap();
+    });
+
+    assert_eq!(attempts, 1);
+    assert_eq!(read_cell(&requests), 111);
+
+    let workers = 8;
+    let increments = 50;
+    let total_attempts: usize = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut attempts = 0;
+                    for _ in 0..increments {
+                        Tx::run(|tx| -> Result<_> {
+                            attempts += 1;
+                            tx.commute(&requests, |n| *n += 1)
+                        })
+                        .unwrap();
+                    }
+                    attempts
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    });
+
+    assert_eq!(total_attempts, workers * increments);
+    assert_eq!(read_cell(&requests), 111 + workers * increments);
+}
+
+#[test]
+fn read_of_commuted_cell() {
+    let counter = StmCell::new(5);
+
+    let observed = Tx::run(|tx| -> Result<_> {
+        tx.commute(&counter, |n| *n *= 2)?;
+        // The deferred change is visible to the transaction itself
+        let observed = **tx.track(&counter)?;
+        // The cell has been read, so the change is applied right away
+        tx.commute(&counter, |n| *n += 1)?;
+        assert_eq!(**tx.track(&counter)?, 11);
+        Ok(observed)
+    })
+    .unwrap();
+    assert_eq!(observed, 10);
+    assert_eq!(read_cell(&counter), 11);
+
+    let rolled_back = Tx::run(|tx| -> Result<_> {
+        tx.commute(&counter, |n| *n += 1)?;
+        let result = tx.nested(|tx| -> Result<_> {
+            tx.commute(&counter, |n| *n += 100)?;
+            Tx::abort()
+        });
+        assert_matches!(result, Err(Error::TransactionAbort(())));
+        let tx_counter = tx.track(&counter)?;
+        assert_matches!(
+            tx.commute::<_, _, ()>(&counter, |n| *n += 1),
+            Err(Error::TransactionVariableIsInUse(_))
+        );
+        Ok(**tx_counter)
+    })
+    .unwrap();
+    assert_eq!(rolled_back, 12);
+    assert_eq!(read_cell(&counter), 12);
+}
+
+#[test]
+fn labels() {
+    let balance = StmCell::with_label(format!("balance:{}", "alice"), 100);
+    let var_id = balance.var_id();
+    assert_eq!(var_id.label().as_deref(), Some("balance:alice"));
+    assert_eq!(var_id.to_string(), "balance:alice");
+    assert!(format!("{balance:?}").ends_with(", \"balance:alice\"))"));
+
+    let queue = StmQueue::<i32>::with_label("jobs");
+    assert!(format!("{queue:?}").ends_with(", \"jobs\"))"));
+    let map = StmMap::<i32, i32>::with_label("index");
+    assert_eq!(map.var_id().to_string(), "index");
+
+    // Containers can be labeled when built from items
+    let queue = StmQueue::from_iter_with_label("backlog", [1, 2]);
+    assert_eq!(queue.var_id().to_string(), "backlog");
+    let map = StmMap::from_iter_with_label("prices", [("apple", 3)]);
+    assert_eq!(map.var_id().to_string(), "prices");
+    let items = Tx::run(|tx| -> Result<_> {
+        let map = tx.track(&map)?;
+        let queue = tx.track(&queue)?;
+        Ok((
+            map.get("apple")?.as_deref().copied(),
+            queue.peek()?.as_deref().copied(),
+        ))
+    })
+    .unwrap();
+    assert_eq!(items, (Some(3), Some(1)));
+
+    // An unlabeled variable is shown by its ID
+    let unlabeled = StmCell::new(0);
+    assert!(unlabeled.var_id().label().is_none());
+    assert!(unlabeled.var_id().to_string().starts_with('#'));
+
+    let result = Tx::run(|tx| -> Result {
+        let _first = tx.track(&balance)?;
+        let _second = tx.track(&balance)?;
+        Ok(())
+    });
+    assert_matches!(result, Err(Error::TransactionVariableIsInUse(id)) if id == var_id);
+    assert!(result.unwrap_err().to_string().contains("`balance:alice`"));
+
+    // The label lives as long as any clone of the variable
+    let clone = balance.clone();
+    drop(balance);
+    assert!(var_id.label().is_some());
+    drop(clone);
+    assert!(var_id.label().is_none());
+}
@@ -0,0 +1,83 @@
+// Every test uses only some of the helpers
+#![allow(dead_code)]
+
+use naive_stm::{track, Result, StmCell, StmVar, Tx, TxOptions};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+/// Commits an increment of the cell
+pub fn increment(cell: &StmCell<i32>) {
+    Tx::run(|tx| -> Result {
+        track!(tx, cell);
+        **cell += 1;
+        Ok(())
+    })
+    .unwrap()
+}
+
+/// Calls `f` while 4 threads keep incrementing the counter
+/// in transactions run with the options
+pub fn under_contention<T>(
+    counter: &StmCell<i32>,
+    options: &TxOptions,
+    f: impl FnOnce() -> T,
+) -> T {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    Tx::run_with_options(options, |tx| -> Result {
+                        track!(tx, counter);
+                        **counter += 1;
+                        Ok(())
+                    })
+                    .unwrap()
+                }
+            });
+        }
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/// Runs the `first` transaction, which is interrupted by the commit of the `second` one.
+/// Returns the number of attempts of the `first` transaction.
+///
+/// The `first` transaction always changes another variable, so it's validated
+/// at commit even if `first` only reads.
+pub fn interleave<V, F, S>(var: &V, first: F, second: S) -> usize
+where
+    V: StmVar + Sync,
+    F: Fn(&mut V::TxVar) -> Result + Sync,
+    S: Fn(&mut V::TxVar) -> Result,
+{
+    let barrier = Barrier::new(2);
+    let written = StmCell::new(0);
+    let mut attempts = 0;
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            Tx::run(|tx| {
+                attempts += 1;
+                first(&mut *tx.track(var)?)?;
+                if attempts == 1 {
+                    barrier.wait();
+                    barrier.wait();
+                }
+                **tx.track(&written)? = attempts;
+                Ok(())
+            })
+            .unwrap()
+        });
+        barrier.wait();
+        Tx::run(|tx| second(&mut *tx.track(var)?)).unwrap();
+        barrier.wait();
+    });
+    attempts
+}
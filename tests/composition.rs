@@ -0,0 +1,171 @@
+use naive_stm::{track, Error, Result, StmCell, StmMap, StmQueue, Tx, TxQueue};
+use std::{thread, time::Duration};
+
+fn pop_or_retry<T: Clone>(queue: &mut TxQueue<T>) -> Result<T> {
+    match queue.pop()? {
+        Some(item) => Ok(item),
+        None => {
+            Tx::retry()?;
+            unreachable!()
+        }
+    }
+}
+
+#[test]
+fn or_else_prioritized_queues() {
+    let high = StmQueue::from_iter(["h1"]);
+    let low = StmQueue::from_iter(["l1", "l2"]);
+
+    let dispatch = || {
+        Tx::run(|tx| {
+            tx.or_else(
+                |tx| pop_or_retry(&mut *tx.track(&high)?),
+                |tx| pop_or_retry(&mut *tx.track(&low)?),
+            )
+        })
+        .unwrap()
+    };
+
+    assert_eq!(dispatch(), "h1");
+    assert_eq!(dispatch(), "l1");
+    assert_eq!(dispatch(), "l2");
+
+    thread::scope(|scope| {
+        let dispatcher = scope.spawn(dispatch);
+        thread::sleep(Duration::from_millis(50));
+        Tx::run(|tx| -> Result {
+            track!(tx, high);
+            high.push("h2");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(dispatcher.join().unwrap(), "h2");
+    });
+}
+
+#[test]
+fn or_else_rolls_back_first_branch() {
+    let counter = StmCell::new(0);
+    let log = StmQueue::new();
+
+    let branch = Tx::run(|tx| {
+        {
+            track!(tx, counter);
+            **counter += 1;
+        }
+        tx.or_else(
+            |tx| -> Result<_> {
+                track!(tx, counter, log);
+                **counter += 10;
+                log.push("first");
+                Tx::retry()?;
+                Ok("first")
+            },
+            |tx| {
+                track!(tx, counter, log);
+                assert_eq!(**counter, 1);
+                assert!(log.is_empty()?);
+                log.push("second");
+                Ok("second")
+            },
+        )
+    })
+    .unwrap();
+
+    assert_eq!(branch, "second");
+    Tx::run(|tx| -> Result {
+        track!(tx, counter, log);
+        assert_eq!(**counter, 1);
+        assert_eq!(log.pop()?, Some("second"));
+        assert_eq!(log.pop()?, None);
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn nested_rolls_back_only_failed_changes() {
+    let accounts = StmMap::new();
+    let batch = [("alice", 10), ("bob", -5), ("carol", 7), ("alice", -20)];
+
+    let rejected = Tx::run(|tx| {
+        let mut rejected = vec![];
+        for (name, amount) in batch {
+            let result = tx.nested(|tx| {
+                track!(tx, accounts);
+                let balance = accounts.get(name)?.as_deref().copied();
+                let balance = balance.unwrap_or(0) + amount;
+                accounts.insert(name, balance);
+                if balance < 0 {
+                    Tx::abort()?;
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => (),
+                Err(Error::TransactionAbort(())) => rejected.push(name),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(rejected)
+    })
+    .unwrap();
+
+    assert_eq!(rejected, vec!["bob", "alice"]);
+    Tx::run(|tx| {
+        track!(tx, accounts);
+        assert_eq!(
+            accounts.iter().collect::<Result<Vec<_>>>()?,
+            vec![("alice", 10), ("carol", 7)]
+        );
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn rolled_back_read_of_commuted_cell_is_validated() {
+    let counter = StmCell::new(0);
+    let log = StmQueue::new();
+    let mut attempts = 0;
+
+    let (result, stats) = Tx::run_with_stats(&Default::default(), |tx| {
+        attempts += 1;
+        tx.commute(&counter, |counter| *counter += 1)?;
+        tx.or_else(
+            |tx| -> Result {
+                if **tx.track(&counter)? < 5 {
+                    Tx::retry()?;
+                }
+                Ok(())
+            },
+            |tx| {
+                tx.track(&log)?.push("low");
+                Ok(())
+            },
+        )?;
+        if attempts == 1 {
+            // The rolled back read is invalidated before the commit
+            thread::scope(|scope| {
+                scope.spawn(|| {
+                    Tx::run(|tx| -> Result {
+                        **tx.track(&counter)? = 100;
+                        Ok(())
+                    })
+                    .unwrap()
+                });
+            });
+        }
+        Ok(())
+    });
+
+    result.unwrap();
+    assert_eq!(stats.attempts, 2);
+    Tx::run(|tx| -> Result {
+        track!(tx, counter, log);
+        assert_eq!(**counter, 101);
+        assert_eq!(log.pop()?, None);
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,123 @@
+use naive_stm::{
+    track, ConstantPause, ContentionManager, Error, Greedy, Karma, Passive,
+    Result, StmCell, Tx, TxOptions, YieldOnly,
+};
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::{increment, under_contention};
+
+/// Contenders are shared by all transactions in the process,
+/// so the tests run one by one to not wait for each other
+fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs a long transaction against a stream of short ones
+/// that use the same contention manager
+fn long_transaction<M, F>(priority: u32, manager: F) -> Result
+where
+    M: ContentionManager + 'static,
+    F: Fn() -> M + Sync,
+{
+    let counter = StmCell::new(0);
+    let short_options = TxOptions {
+        attempts: usize::MAX,
+        retry_policy: Box::new(YieldOnly),
+        contention_manager: Box::new(manager()),
+        ..Default::default()
+    };
+    let long_options = TxOptions {
+        attempts: 4,
+        retry_policy: Box::new(YieldOnly),
+        priority,
+        contention_manager: Box::new(manager()),
+        ..Default::default()
+    };
+
+    under_contention(&counter, &short_options, || {
+        Tx::run_with_options(&long_options, |tx| {
+            track!(tx, counter);
+            thread::sleep(Duration::from_millis(20));
+            **counter += 1_000_000;
+            Ok(())
+        })
+    })
+}
+
+#[test]
+fn long_transaction_is_starved_without_contention_manager() {
+    let _serial = serial();
+    let result = long_transaction(0, || Passive);
+    assert!(matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 4, .. })
+    ));
+}
+
+#[test]
+fn greedy_contention_manager() {
+    let _serial = serial();
+    long_transaction(0, || Greedy).unwrap();
+    long_transaction(1, || Greedy).unwrap();
+}
+
+#[test]
+fn karma_contention_manager() {
+    let _serial = serial();
+    long_transaction(0, || Karma).unwrap();
+    long_transaction(1, || Karma).unwrap();
+}
+
+#[test]
+fn unrelated_transactions_dont_wait() {
+    let _serial = serial();
+    let contended = StmCell::new(0);
+    let unrelated = StmCell::new(0);
+    let contender_options = TxOptions {
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_millis(500),
+            jitter: false,
+        }),
+        priority: 10,
+        contention_manager: Box::new(Greedy),
+        ..Default::default()
+    };
+    let options = TxOptions {
+        contention_manager: Box::new(Greedy),
+        ..Default::default()
+    };
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut attempts = 0;
+            Tx::run_with_options(&contender_options, |tx| -> Result {
+                attempts += 1;
+                let mut tx_contended = tx.track(&contended)?;
+                if attempts == 1 {
+                    increment(&contended);
+                }
+                **tx_contended += 1;
+                Ok(())
+            })
+            .unwrap()
+        });
+        // The contender pauses after its first attempt has failed
+        thread::sleep(Duration::from_millis(50));
+
+        let started_at = Instant::now();
+        Tx::run_with_options(&options, |tx| -> Result {
+            track!(tx, unrelated);
+            **unrelated += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(started_at.elapsed() < Duration::from_millis(250));
+    });
+}
@@ -0,0 +1,179 @@
+#![cfg(feature = "conformance")]
+
+use naive_stm::{
+    conformance::Conformance, LockedTxVar, Result, StmCell, StmMap, StmQueue,
+    StmVar, StmVarId, Tx, TxVar, Version, VersionedValue, Waiter,
+};
+use std::{
+    any::Any,
+    convert::Infallible,
+    sync::{Arc, RwLock, RwLockWriteGuard},
+};
+
+/// A custom container that counts events
+#[derive(Clone)]
+struct StmCounter {
+    var_id: StmVarId,
+    count: Arc<RwLock<VersionedValue<usize>>>,
+}
+
+impl StmCounter {
+    fn new() -> Self {
+        Self {
+            var_id: StmVarId::new(),
+            count: Arc::new(RwLock::new(VersionedValue::new(0))),
+        }
+    }
+}
+
+impl StmVar for StmCounter {
+    type TxVar = TxCounter;
+
+    fn var_id(&self) -> StmVarId {
+        self.var_id
+    }
+
+    fn tx_var(&self, read_version: &Version) -> Result<TxCounter, Infallible> {
+        let count = self.count.read().unwrap();
+        Ok(TxCounter {
+            read_version: read_version.clone(),
+            count: Arc::clone(&self.count),
+            read_count: *count.read(self.var_id, read_version)?,
+            increments: 0,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct TxCounter {
+    read_version: Version,
+    count: Arc<RwLock<VersionedValue<usize>>>,
+    read_count: usize,
+    increments: usize,
+}
+
+impl TxCounter {
+    fn get(&self) -> usize {
+        self.read_count + self.increments
+    }
+
+    fn increment(&mut self) {
+        self.increments += 1
+    }
+}
+
+impl TxVar for TxCounter {
+    fn lock(&mut self) -> Box<dyn LockedTxVar + '_> {
+        Box::new(LockedTxCounter {
+            read_version: &self.read_version,
+            count: self.count.write().unwrap(),
+            new_count: self.read_count + self.increments,
+            has_changes: self.increments > 0,
+        })
+    }
+
+    fn has_changes(&self) -> bool {
+        self.increments > 0
+    }
+
+    fn subscribe(&self, waiter: &Arc<Waiter>) -> bool {
+        let mut count = self.count.write().unwrap();
+        count.subscribe(&self.read_version, waiter)
+    }
+
+    fn snapshot(&self) -> Box<dyn TxVar> {
+        Box::new(self.clone())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+struct LockedTxCounter<'a> {
+    read_version: &'a Version,
+    count: RwLockWriteGuard<'a, VersionedValue<usize>>,
+    new_count: usize,
+    has_changes: bool,
+}
+
+impl LockedTxVar for LockedTxCounter<'_> {
+    fn can_commit(&self) -> bool {
+        self.count.version() <= self.read_version
+    }
+
+    fn commit(&mut self, write_version: &Version) {
+        if self.has_changes {
+            *self.count.data_mut() = self.new_count;
+            self.count.update_version(write_version)
+        }
+    }
+}
+
+/// Counts the items of a queue or a map
+fn count<T>(mut iter: impl Iterator<Item = Result<T>>) -> Result<usize> {
+    iter.try_fold(0, |count, item| item.map(|_| count + 1))
+}
+
+#[test]
+fn custom_variable() {
+    let counter = StmCounter::new();
+    let count = Tx::run(|tx| -> Result<_> {
+        let mut counter = tx.track(&counter)?;
+        counter.increment();
+        counter.increment();
+        Ok(counter.get())
+    })
+    .unwrap();
+    assert_eq!(count, 2);
+
+    Conformance::new(
+        StmCounter::new,
+        |counter| {
+            counter.increment();
+            Ok(())
+        },
+        |counter| Ok(counter.get()),
+    )
+    .check_all();
+}
+
+#[test]
+fn cell_conforms() {
+    Conformance::new(
+        || StmCell::new(0),
+        |cell| {
+            **cell += 1;
+            Ok(())
+        },
+        |cell| Ok(**cell),
+    )
+    .check_all();
+}
+
+#[test]
+fn queue_conforms() {
+    Conformance::new(
+        StmQueue::new,
+        |queue| {
+            queue.push(());
+            Ok(())
+        },
+        |queue| count(queue.iter()),
+    )
+    .check_all();
+}
+
+#[test]
+fn map_conforms() {
+    Conformance::new(
+        StmMap::new,
+        |map| {
+            let key = count(map.iter())?;
+            map.insert(key, ());
+            Ok(())
+        },
+        |map| count(map.iter()),
+    )
+    .check_all();
+}
@@ -0,0 +1,214 @@
+use assert_matches::assert_matches;
+use naive_stm::{Conflict, Error, LiftError, StmCell, StmMap, Tx, TxOptions};
+use std::{convert::Infallible, thread};
+
+#[derive(Debug, PartialEq)]
+enum TransferError {
+    UnknownAccount(&'static str),
+    InsufficientFunds { balance: u64 },
+}
+
+fn transfer(
+    accounts: &StmMap<&'static str, u64>,
+    from: &'static str,
+    to: &'static str,
+    amount: u64,
+) -> Result<(), Error<TransferError>> {
+    Tx::run(|tx| {
+        let mut accounts = tx.track(accounts)?;
+        let Some(balance) = accounts.get_mut(from)? else {
+            return Tx::abort_with(TransferError::UnknownAccount(from));
+        };
+        if *balance < amount {
+            let balance = *balance;
+            return Tx::abort_with(TransferError::InsufficientFunds {
+                balance,
+            });
+        }
+        *balance -= amount;
+        match accounts.get_mut(to)? {
+            Some(balance) => *balance += amount,
+            None => Tx::abort_with(TransferError::UnknownAccount(to))?,
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn custom_errors() {
+    let accounts = StmMap::from_iter([("alice", 100), ("bob", 0)]);
+
+    assert_matches!(
+        transfer(&accounts, "alice", "carol", 10),
+        Err(Error::TransactionAbort(TransferError::UnknownAccount(
+            "carol"
+        )))
+    );
+    assert_matches!(
+        transfer(&accounts, "bob", "alice", 10),
+        Err(Error::TransactionAbort(TransferError::InsufficientFunds {
+            balance: 0
+        }))
+    );
+
+    // Conflicts are still retried by the runner
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..25 {
+                    transfer(&accounts, "alice", "bob", 1).unwrap();
+                }
+            });
+        }
+    });
+    let balances = Tx::run(|tx| -> Result<_, Error> {
+        let accounts = tx.track(&accounts)?;
+        Ok((
+            accounts.get("alice")?.unwrap().into_owned(),
+            accounts.get("bob")?.unwrap().into_owned(),
+        ))
+    });
+    assert_eq!(balances.unwrap(), (0, 100));
+
+    // Waiting for a change works the same way
+    let cell = StmCell::new(0);
+    let mut attempts = 0;
+    let result: Result<_, Error<TransferError>> = Tx::run(|tx| {
+        attempts += 1;
+        let value = **tx.track(&cell)?;
+        if attempts == 1 {
+            thread::scope(|scope| {
+                scope.spawn(|| {
+                    Tx::run(|tx| -> Result<(), Error> {
+                        **tx.track(&cell)? += 1;
+                        Ok(())
+                    })
+                });
+            });
+            Tx::retry()?;
+        }
+        Ok(value)
+    });
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[test]
+fn map_abort() {
+    let error = Error::TransactionAbort(TransferError::UnknownAccount("dave"));
+    assert_matches!(
+        error.map_abort(|error| format!("{error:?}")),
+        Error::TransactionAbort(error) if error == "UnknownAccount(\"dave\")"
+    );
+    assert_matches!(
+        Error::<()>::DeadlineExceeded.map_abort(|()| 5),
+        Error::DeadlineExceeded
+    );
+}
+
+#[test]
+fn std_error() {
+    fn run() -> Result<(), Box<dyn std::error::Error>> {
+        Tx::run(|_| Tx::abort_with("failure"))?;
+        Ok(())
+    }
+    let error = run().unwrap_err();
+    assert_eq!(error.to_string(), "Transaction was explicitly aborted");
+    assert!(error.source().is_none());
+}
+
+#[test]
+fn lift_errors() {
+    /// A helper that can't abort
+    fn balance(
+        tx: &Tx,
+        accounts: &StmMap<&'static str, u64>,
+        name: &'static str,
+    ) -> Result<Option<u64>, Error<Infallible>> {
+        Ok(tx.track_shared(accounts)?.get(name)?.as_deref().copied())
+    }
+
+    let accounts = StmMap::from_iter([("alice", 100)]);
+    let result = Tx::run(|tx| {
+        let alice = balance(tx, &accounts, "alice").lift()?;
+        if balance(tx, &accounts, "bob").lift()?.is_none() {
+            Tx::abort_with(TransferError::UnknownAccount("bob"))?;
+        }
+        Ok(alice)
+    });
+    assert_matches!(
+        result,
+        Err(Error::TransactionAbort(TransferError::UnknownAccount(
+            "bob"
+        )))
+    );
+}
+
+#[test]
+fn conflicts_of_failed_run() {
+    let balance = StmCell::with_label("balance:alice", 0);
+    let limits = StmMap::with_label("limits:alice");
+    let unlabelled = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 6,
+        ..Default::default()
+    };
+    let mut attempts = 0;
+    let result = Tx::run_with_options(&options, |tx| -> Result<(), Error> {
+        attempts += 1;
+        let mut tx_balance = tx.track(&balance)?;
+        let tx_limits = tx.track(&limits)?;
+        let mut tx_unlabelled = tx.track(&unlabelled)?;
+        match attempts {
+            // The commit fails
+            1..=3 => Tx::run(|tx| {
+                **tx.track(&balance)? += 1;
+                Ok(())
+            })?,
+            // Reading fails
+            4 | 5 => Tx::run(|tx| {
+                tx.track(&limits)?.insert("daily", attempts);
+                Ok(())
+            })?,
+            _ => Tx::run(|tx| {
+                **tx.track(&unlabelled)? += 1;
+                Ok(())
+            })?,
+        }
+        tx_limits.get("daily")?;
+        **tx_balance += 1;
+        **tx_unlabelled += 1;
+        Ok(())
+    });
+    let unlabelled_id = unlabelled.var_id();
+    drop((balance, limits, unlabelled));
+
+    let error = result.unwrap_err();
+    let Error::TooManyTransactionRetryAttempts {
+        attempts: 6,
+        conflicts,
+    } = &error
+    else {
+        panic!("Unexpected error: {error:?}")
+    };
+    let conflicts: Vec<_> = conflicts
+        .iter()
+        .map(|Conflict { label, count, .. }| (label.as_deref(), *count))
+        .collect();
+    assert_eq!(
+        conflicts,
+        [
+            (Some("balance:alice"), 3),
+            (Some("limits:alice"), 2),
+            (None, 1)
+        ]
+    );
+    // The labels are kept after the variables are dropped
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "The maximum number (6) of attempts for the transaction \
+            has been reached. Conflicts: `balance:alice` (3 times), \
+            `limits:alice` (2 times), `{unlabelled_id}` (1 time)"
+        )
+    );
+}
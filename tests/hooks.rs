@@ -0,0 +1,186 @@
+use assert_matches::assert_matches;
+use naive_stm::{track, ConstantPause, Error, Result, StmCell, Tx, TxOptions};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::increment;
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Registers hooks that record the attempt they were registered by
+fn register_hooks(tx: &Tx, log: &Log, attempt: usize) {
+    let commit_log = Arc::clone(log);
+    tx.on_commit(move || {
+        commit_log.lock().unwrap().push(format!("commit {attempt}"))
+    });
+    let abort_log = Arc::clone(log);
+    tx.on_abort(move || {
+        abort_log.lock().unwrap().push(format!("abort {attempt}"))
+    });
+}
+
+#[test]
+fn commit_hooks_of_the_last_attempt() {
+    let cell = StmCell::new(0);
+    let log = Log::default();
+    let mut attempts = 0;
+
+    Tx::run(|tx| -> Result {
+        attempts += 1;
+        register_hooks(tx, &log, attempts);
+        let mut tx_cell = tx.track(&cell)?;
+        if attempts < 3 {
+            increment(&cell);
+        }
+        **tx_cell += 1;
+        // Hooks are not called before the commit
+        assert!(log.lock().unwrap().is_empty());
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(attempts, 3);
+    assert_eq!(*log.lock().unwrap(), vec!["commit 3"]);
+}
+
+#[test]
+fn abort_hooks() {
+    let cell = StmCell::new(0);
+    let log = Log::default();
+
+    let result = Tx::run(|tx| {
+        register_hooks(tx, &log, 1);
+        track!(tx, cell);
+        **cell += 1;
+        Tx::abort()
+    });
+    assert_matches!(result, Err(Error::TransactionAbort(())));
+    assert_eq!(*log.lock().unwrap(), vec!["abort 1"]);
+
+    // The transaction runs out of attempts
+    log.lock().unwrap().clear();
+    let mut attempts = 0;
+    let options = TxOptions {
+        attempts: 3,
+        ..Default::default()
+    };
+    let result = Tx::run_with_options(&options, |tx| -> Result {
+        attempts += 1;
+        register_hooks(tx, &log, attempts);
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 3, .. })
+    );
+    assert_eq!(*log.lock().unwrap(), vec!["abort 3"]);
+}
+
+#[test]
+fn abort_hooks_of_interrupted_wait() {
+    let cell = StmCell::new(0);
+    let log = Log::default();
+
+    // The deadline is exceeded during the pause after a conflict
+    let options = TxOptions {
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_millis(100),
+            jitter: false,
+        }),
+        deadline: Some(Instant::now() + Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+    let result = Tx::run_with_options(&options, |tx| -> Result {
+        attempts += 1;
+        register_hooks(tx, &log, attempts);
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+    assert_eq!(attempts, 1);
+    assert_eq!(*log.lock().unwrap(), vec!["abort 1"]);
+
+    // The deadline is exceeded while waiting after `Tx::retry`
+    log.lock().unwrap().clear();
+    let options = TxOptions {
+        deadline: Some(Instant::now() + Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let result: Result<(), _> =
+        Tx::run_with_options(&options, |tx| -> Result<_> {
+            register_hooks(tx, &log, 1);
+            track!(tx, cell);
+            if **cell > 0 {
+                Tx::retry()
+            } else {
+                Ok(())
+            }
+        });
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+    assert_eq!(*log.lock().unwrap(), vec!["abort 1"]);
+}
+
+#[tokio::test]
+async fn abort_hooks_of_interrupted_async_wait() {
+    let cell = Arc::new(StmCell::new(1));
+    let log = Log::default();
+    let options = TxOptions {
+        deadline: Some(Instant::now() + Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let result = tokio::spawn({
+        let log = Arc::clone(&log);
+        async move {
+            Tx::run_async_with_options(&options, |tx| -> Result<_> {
+                register_hooks(tx, &log, 1);
+                if **tx.track(&*cell)? > 0 {
+                    Tx::retry()
+                } else {
+                    Ok(())
+                }
+            })
+            .await
+        }
+    })
+    .await
+    .unwrap();
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+    assert_eq!(*log.lock().unwrap(), vec!["abort 1"]);
+}
+
+#[test]
+fn rolled_back_hooks_are_dropped() {
+    let log = Log::default();
+
+    Tx::run(|tx| {
+        register_hooks(tx, &log, 1);
+        let result = tx.nested(|tx| {
+            register_hooks(tx, &log, 2);
+            Tx::abort()
+        });
+        assert_matches!(result, Err(Error::TransactionAbort(())));
+        tx.or_else(
+            |tx| -> Result<_> {
+                register_hooks(tx, &log, 3);
+                Tx::retry()
+            },
+            |tx| {
+                register_hooks(tx, &log, 4);
+                Ok(())
+            },
+        )
+    })
+    .unwrap();
+
+    assert_eq!(*log.lock().unwrap(), vec!["commit 1", "commit 4"]);
+}
@@ -0,0 +1,125 @@
+use assert_matches::assert_matches;
+use naive_stm::{
+    track, CancellationToken, ConstantPause, Error, Result, StmCell, StmQueue,
+    Tx, TxOptions,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+fn pop_or_retry(queue: &StmQueue<i32>) -> impl FnMut(&Tx) -> Result<i32> + '_ {
+    move |tx| {
+        track!(tx, queue);
+        match queue.pop()? {
+            Some(item) => Ok(item),
+            None => {
+                Tx::retry()?;
+                unreachable!()
+            }
+        }
+    }
+}
+
+#[test]
+fn deadline_interrupts_pause() {
+    let cell = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 100,
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_secs(10),
+            jitter: false,
+        }),
+        deadline: Some(Instant::now() + Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    let start = Instant::now();
+    let result = Tx::run_with_options(&options, |tx| -> Result {
+        attempts += 1;
+        let mut tx_cell = tx.track(&cell)?;
+        // A concurrent commit makes every attempt fail
+        Tx::run(|tx| -> Result {
+            track!(tx, cell);
+            **cell += 1;
+            Ok(())
+        })
+        .unwrap();
+        **tx_cell += 1;
+        Ok(())
+    });
+
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+    assert_eq!(attempts, 1);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn deadline_interrupts_retry() {
+    let queue = StmQueue::new();
+    let options = TxOptions {
+        deadline: Some(Instant::now() + Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let result = Tx::run_with_options(&options, pop_or_retry(&queue));
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+}
+
+#[test]
+fn cancellation_interrupts_retry() {
+    let queue = StmQueue::new();
+    let token = CancellationToken::new();
+    let options = TxOptions {
+        cancellation: Some(token.clone()),
+        ..Default::default()
+    };
+
+    let result = thread::scope(|scope| {
+        let consumer = scope
+            .spawn(|| Tx::run_with_options(&options, pop_or_retry(&queue)));
+        thread::sleep(Duration::from_millis(50));
+        token.cancel();
+        consumer.join().unwrap()
+    });
+
+    assert_matches!(result, Err(Error::Cancelled));
+    assert!(token.is_cancelled());
+
+    // The transaction is not attempted with a cancelled token
+    let result = Tx::run_with_options(&options, |_| -> Result {
+        panic!("The transaction must not be attempted")
+    });
+    assert_matches!(result, Err(Error::Cancelled));
+}
+
+#[tokio::test]
+async fn cancellation_interrupts_async_retry() {
+    let queue = StmQueue::new();
+    let token = CancellationToken::new();
+    let options = TxOptions {
+        cancellation: Some(token.clone()),
+        ..Default::default()
+    };
+
+    let consumer = Tx::run_async_with_options(&options, pop_or_retry(&queue));
+    let canceller = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        token.cancel();
+    };
+    let (result, ()) = tokio::join!(consumer, canceller);
+
+    assert_matches!(result, Err(Error::Cancelled));
+}
+
+#[tokio::test]
+async fn deadline_interrupts_async_retry() {
+    let queue = StmQueue::new();
+    let options = TxOptions {
+        deadline: Some(Instant::now() + Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let result =
+        Tx::run_async_with_options(&options, pop_or_retry(&queue)).await;
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+}
@@ -0,0 +1,92 @@
+use assert_matches::assert_matches;
+use naive_stm::{track, Error, Result, StmCell, Tx, TxOptions};
+
+mod common;
+
+use common::{increment, under_contention};
+
+#[test]
+fn irrevocable_transaction_is_attempted_once() {
+    let counter = StmCell::new(0);
+    let mut side_effects = Vec::new();
+
+    under_contention(&counter, &TxOptions::default(), || {
+        for _ in 0..100 {
+            Tx::run_irrevocable(|tx| -> Result {
+                assert!(tx.is_irrevocable());
+                track!(tx, counter);
+                side_effects.push(**counter);
+                **counter += 1;
+                Ok(())
+            })
+            .unwrap();
+        }
+    });
+
+    assert_eq!(side_effects.len(), 100);
+    // Every irrevocable transaction has changed the counter
+    assert!(side_effects.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn aborted_irrevocable_transaction() {
+    let cell = StmCell::new(0);
+
+    let result = Tx::run_irrevocable(|tx| {
+        track!(tx, cell);
+        **cell += 1;
+        Tx::abort()
+    });
+    assert_matches!(result, Err(Error::TransactionAbort(())));
+
+    // Other transactions can commit after the irrevocable one
+    Tx::run(|tx| -> Result {
+        track!(tx, cell);
+        assert_eq!(**cell, 0);
+        **cell += 1;
+        Ok(())
+    })
+    .unwrap();
+    let value =
+        Tx::run_irrevocable(|tx| -> Result<_> { Ok(**tx.track(&cell)?) })
+            .unwrap();
+    assert_eq!(value, 1);
+    let irrevocable = Tx::run(|tx| -> Result<bool> { Ok(tx.is_irrevocable()) });
+    assert!(!irrevocable.unwrap());
+}
+
+#[test]
+#[should_panic(expected = "Irrevocable transaction can't be retried")]
+fn irrevocable_transaction_cant_be_retried() {
+    let _ = Tx::run_irrevocable(|_| Tx::retry::<()>());
+}
+
+#[test]
+fn nested_transactions_of_irrevocable_transaction() {
+    let cell = StmCell::new(0);
+    let other = StmCell::new(0);
+
+    Tx::run_irrevocable(|tx| -> Result {
+        track!(tx, cell);
+        increment(&other);
+        **cell += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        Tx::run(|tx| -> Result<_> { Ok(**tx.track(&other)?) }).unwrap(),
+        1
+    );
+
+    // The irrevocable transaction can't be attempted again
+    let result = Tx::run_irrevocable(|tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 1, .. })
+    );
+}
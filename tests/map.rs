@@ -1,16 +1,20 @@
 #![allow(clippy::disallowed_names)]
 
 use naive_stm::{
-    track, Result, Tx, {StmMap, TxMap},
+    track, Error, Result, Tx, {StmMap, TxMap},
 };
 use std::{collections::BTreeMap, thread, time::Duration};
 
+mod common;
+
+use common::interleave;
+
 fn drain_map<K, V>(map: &StmMap<K, V>) -> BTreeMap<K, V>
 where
     K: Ord + Clone + 'static,
     V: Clone + 'static,
 {
-    Tx::run(|tx| {
+    Tx::run(|tx| -> Result<_> {
         track! {tx, map};
         let mut res = BTreeMap::new();
         while let Some(key) = map.first_key()? {
@@ -171,3 +175,88 @@ fn maps_grouping() {
         .into()
     );
 }
+
+#[test]
+fn per_key_conflicts() {
+    let map = || StmMap::from_iter([("a", 1), ("b", 2)]);
+    let get_a = |m: &mut TxMap<_, _>| {
+        m.get("a")?;
+        Ok(())
+    };
+    let update_a = |m: &mut TxMap<_, _>| {
+        *m.get_mut("a")?.unwrap() += 1;
+        Ok(())
+    };
+    let update_b = |m: &mut TxMap<_, _>| {
+        *m.get_mut("b")?.unwrap() += 1;
+        Ok(())
+    };
+    let insert_c = |m: &mut TxMap<_, _>| {
+        m.insert("c", 3);
+        Ok(())
+    };
+    let check_c = |m: &mut TxMap<_, _>| {
+        m.contains_key("c")?;
+        Ok(())
+    };
+    let remove_b = |m: &mut TxMap<_, _>| {
+        m.remove("b");
+        Ok(())
+    };
+    let first_key = |m: &mut TxMap<_, _>| {
+        m.first_key()?;
+        Ok(())
+    };
+
+    assert_eq!(interleave(&map(), get_a, update_b), 1);
+    assert_eq!(interleave(&map(), update_a, update_b), 1);
+    assert_eq!(interleave(&map(), get_a, insert_c), 1);
+    assert_eq!(interleave(&map(), get_a, update_a), 2);
+    assert_eq!(interleave(&map(), update_a, update_a), 2);
+    assert_eq!(interleave(&map(), check_c, insert_c), 2);
+    assert_eq!(interleave(&map(), first_key, update_b), 1);
+    assert_eq!(interleave(&map(), first_key, insert_c), 2);
+    assert_eq!(interleave(&map(), first_key, remove_b), 2);
+}
+
+fn price(
+    tx: &Tx,
+    prices: &StmMap<&'static str, u32>,
+    item: &str,
+) -> Result<u32> {
+    let prices = tx.track_shared(prices)?;
+    let price = prices.get(item)?.map_or(0, |price| *price);
+    Ok(price)
+}
+
+#[test]
+fn shared_handles() {
+    let prices = StmMap::from_iter([("apple", 3), ("pear", 5)]);
+
+    let total = Tx::run(|tx| -> Result<_> {
+        let all_prices = tx.track_shared(&prices)?;
+        // Helpers re-track the map while another shared handle is alive
+        let total = price(tx, &prices, "apple")? + price(tx, &prices, "pear")?;
+        assert_eq!(all_prices.iter::<()>().count(), 2);
+
+        let result: Result = tx.track(&prices).map(|_| ());
+        assert!(matches!(
+            result,
+            Err(Error::TransactionVariableIsInUse(var_id)) if var_id == prices.var_id()
+        ));
+        drop(all_prices);
+
+        // The map can be changed once the shared handles are dropped
+        tx.track(&prices)?.insert("plum", 7);
+        let prices_with_plum = tx.track_shared(&prices)?;
+        let result: Result = tx.track(&prices).map(|_| ());
+        assert!(result.is_err());
+        assert_eq!(price(tx, &prices, "plum")?, 7);
+        assert_eq!(prices_with_plum.iter::<()>().count(), 3);
+        Ok(total)
+    })
+    .unwrap();
+
+    assert_eq!(total, 8);
+    assert_eq!(drain_map(&prices).len(), 3);
+}
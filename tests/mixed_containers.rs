@@ -1,5 +1,6 @@
 use naive_stm::{
-    track, Error, Result, StmCell, StmMap, StmQueue, Tx, TxOptions,
+    track, ConstantPause, Error, Result, StmCell, StmMap, StmQueue, Tx,
+    TxOptions,
 };
 use rand::{seq::SliceRandom, Rng};
 use std::{
@@ -14,8 +15,11 @@ fn mixed_containers() {
     let keys = ["b2", "d9", "a1", "a2", "c0", "b5", "b7", "c6"];
     let tx_opts = TxOptions {
         attempts: 20,
-        retry_pause: Duration::from_micros(100),
-        pause_jitter: true,
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_micros(100),
+            jitter: true,
+        }),
+        ..Default::default()
     };
 
     // Each worker will pass some amount of fuel from `source` to next containers
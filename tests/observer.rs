@@ -0,0 +1,139 @@
+use assert_matches::assert_matches;
+use naive_stm::{
+    Error, Result, StmCell, Tx, TxEvent, TxObserver, TxOptions, YieldOnly,
+};
+use std::{
+    sync::{Mutex, Once},
+    thread::{self, ThreadId},
+};
+
+mod common;
+
+use common::increment;
+
+static EVENTS: Mutex<Vec<(ThreadId, TxEvent)>> = Mutex::new(Vec::new());
+
+struct Recorder;
+
+impl TxObserver for Recorder {
+    fn on_event(&self, event: &TxEvent) {
+        let thread_id = thread::current().id();
+        EVENTS.lock().unwrap().push((thread_id, event.clone()))
+    }
+}
+
+/// Tests run concurrently, so the observer is shared by all of them,
+/// and each test only checks the events of its own thread
+fn take_events() -> Vec<TxEvent> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| Tx::set_observer(Recorder));
+    let thread_id = thread::current().id();
+    let mut events = EVENTS.lock().unwrap();
+    let (own, others) = events
+        .drain(..)
+        .partition(|(event_thread_id, _)| *event_thread_id == thread_id);
+    *events = others;
+    own.into_iter().map(|(_, event)| event).collect()
+}
+
+/// Commits a change of the cell in another thread
+fn increment_in_thread(cell: &StmCell<i32>) {
+    thread::scope(|scope| {
+        scope.spawn(|| increment(cell));
+    })
+}
+
+#[test]
+fn conflict_and_commit_events() {
+    take_events();
+    let cell_a = StmCell::new(0);
+    let cell_b = StmCell::new(0);
+    let options = TxOptions {
+        retry_policy: Box::new(YieldOnly),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    let ((), version) = Tx::run_with_commit_version(&options, |tx| -> Result {
+        attempts += 1;
+        let a = tx.track(&cell_a)?;
+        match attempts {
+            // Fails at commit
+            1 => increment_in_thread(&cell_a),
+            // Fails when `cell_b` is tracked
+            2 => increment_in_thread(&cell_b),
+            _ => (),
+        }
+        **tx.track(&cell_b)? += **a;
+        Ok(())
+    })
+    .unwrap();
+
+    let a = cell_a.var_id();
+    let b = cell_b.var_id();
+    assert_eq!(
+        take_events(),
+        vec![
+            TxEvent::AttemptStarted { attempt: 1 },
+            TxEvent::VarTracked { var_id: a },
+            TxEvent::VarTracked { var_id: b },
+            TxEvent::CommitFailed { var_id: a },
+            TxEvent::AttemptStarted { attempt: 2 },
+            TxEvent::VarTracked { var_id: a },
+            TxEvent::ConflictDetected { var_id: b },
+            TxEvent::AttemptStarted { attempt: 3 },
+            TxEvent::VarTracked { var_id: a },
+            TxEvent::VarTracked { var_id: b },
+            TxEvent::Committed { version },
+        ]
+    );
+}
+
+#[test]
+fn abort_events() {
+    take_events();
+    let cell = StmCell::new(0);
+
+    let result = Tx::run(|tx| {
+        tx.commute(&cell, |value| *value += 1)?;
+        Tx::abort()
+    });
+    assert_matches!(result, Err(Error::TransactionAbort(())));
+    assert_eq!(
+        take_events(),
+        vec![
+            TxEvent::AttemptStarted { attempt: 1 },
+            TxEvent::VarTracked {
+                var_id: cell.var_id()
+            },
+            TxEvent::Aborted,
+        ]
+    );
+
+    // The transaction runs out of attempts
+    let options = TxOptions {
+        attempts: 2,
+        retry_policy: Box::new(YieldOnly),
+        ..Default::default()
+    };
+    let result = Tx::run_with_options(&options, |tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment_in_thread(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 2, .. })
+    );
+    let events = take_events();
+    assert_eq!(
+        events[events.len() - 2..],
+        [
+            TxEvent::CommitFailed {
+                var_id: cell.var_id()
+            },
+            TxEvent::Aborted,
+        ]
+    );
+}
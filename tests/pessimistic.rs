@@ -0,0 +1,111 @@
+use naive_stm::{track, Result, StmCell, StmQueue, Tx, TxOptions, YieldOnly};
+use std::{thread, time::Duration};
+
+mod common;
+
+use common::{increment, under_contention};
+
+#[test]
+fn pessimistic_attempt_after_conflicts() {
+    let counter = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 5,
+        retry_policy: Box::new(YieldOnly),
+        pessimistic_after: Some(3),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    let (result, stats) =
+        under_contention(&counter, &TxOptions::default(), || {
+            Tx::run_with_stats(&options, |tx| -> Result {
+                attempts += 1;
+                let mut tx_counter = tx.track(&counter)?;
+                if attempts <= 3 {
+                    // The optimistic attempts surely fail. The pessimistic one
+                    // could only be failed by such nested commit, since
+                    // the concurrent transactions are blocked while it runs.
+                    increment(&counter);
+                }
+                // A long transaction that is always outpaced by the short ones
+                thread::sleep(Duration::from_millis(10));
+                **tx_counter += 1_000_000;
+                Ok(())
+            })
+        });
+
+    result.unwrap();
+    assert_eq!(attempts, 4);
+    assert_eq!(stats.attempts, 4);
+    assert_eq!(stats.conflicts.len(), 3);
+    assert_eq!(stats.pessimistic_attempts, 1);
+}
+
+#[test]
+fn pessimistic_attempt_can_be_retried() {
+    let queue = StmQueue::new();
+    let options = TxOptions {
+        pessimistic_after: Some(0),
+        ..Default::default()
+    };
+
+    let (result, stats) = thread::scope(|scope| {
+        scope.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            Tx::run(|tx| -> Result {
+                track!(tx, queue);
+                queue.push(1);
+                Ok(())
+            })
+            .unwrap()
+        });
+        // The commit token is released while the transaction waits
+        // for a change of the queue
+        Tx::run_with_stats(&options, |tx| -> Result<i32> {
+            track!(tx, queue);
+            match queue.pop()? {
+                Some(item) => Ok(item),
+                None => {
+                    Tx::retry()?;
+                    unreachable!()
+                }
+            }
+        })
+    });
+
+    assert_eq!(result.unwrap(), 1);
+    assert_eq!(stats.attempts, 2);
+    assert_eq!(stats.pessimistic_attempts, 2);
+}
+
+#[test]
+fn nested_transactions_of_pessimistic_attempt() {
+    let cell = StmCell::new(0);
+    let other = StmCell::new(0);
+    let options = TxOptions {
+        pessimistic_after: Some(0),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    let (result, stats) = Tx::run_with_stats(&options, |tx| -> Result {
+        attempts += 1;
+        // Nested transactions don't wait for the commit token of the attempt
+        increment(&other);
+        let mut tx_cell = tx.track(&cell)?;
+        if attempts == 1 {
+            // The change of a tracked variable fails the attempt
+            increment(&cell);
+        }
+        **tx_cell += 10;
+        Ok(())
+    });
+
+    result.unwrap();
+    assert_eq!(stats.attempts, 2);
+    assert_eq!(stats.pessimistic_attempts, 2);
+    let values = Tx::run(|tx| -> Result<_> {
+        Ok((**tx.track(&cell)?, **tx.track(&other)?))
+    });
+    assert_eq!(values.unwrap(), (11, 2));
+}
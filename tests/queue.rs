@@ -1,13 +1,17 @@
 use assert_matches::assert_matches;
-use naive_stm::{track, StmQueue, Tx};
+use naive_stm::{track, Result, StmQueue, Tx, TxQueue};
 use rand::seq::SliceRandom;
 use std::{
     iter, thread,
     time::{Duration, Instant},
 };
 
+mod common;
+
+use common::interleave;
+
 fn drain_queue<T: Clone + 'static>(queue: &StmQueue<T>) -> Vec<T> {
-    Tx::run(|tx| {
+    Tx::run(|tx| -> Result<_> {
         track! {tx, queue};
         let mut items = vec![];
         while let Some(item) = queue.pop()? {
@@ -47,7 +51,7 @@ fn queue_forwarding() {
                     let start_time = Instant::now();
                     let mut items_forwarded = 0;
                     loop {
-                        items_forwarded += Tx::run(|tx| {
+                        items_forwarded += Tx::run(|tx| -> Result<_> {
                             let mut tx_items_forwarded = 0;
                             for pipeline in worker_pipelines {
                                 let mut from_queue = tx.track(pipeline[0])?;
@@ -96,3 +100,104 @@ fn queue_forwarding() {
         ]
     );
 }
+
+#[test]
+fn head_and_tail_conflicts() {
+    let queue = || StmQueue::from_iter([1, 2]);
+    let push = |q: &mut TxQueue<_>| {
+        q.push(3);
+        Ok(())
+    };
+    let pop = |q: &mut TxQueue<_>| {
+        q.pop()?;
+        Ok(())
+    };
+    let pop_and_push = |q: &mut TxQueue<_>| {
+        let item = q.pop()?.unwrap();
+        q.push(item);
+        Ok(())
+    };
+    let drain = |q: &mut TxQueue<_>| {
+        while q.pop()?.is_some() {}
+        Ok(())
+    };
+    let peek = |q: &mut TxQueue<_>| {
+        q.peek()?;
+        Ok(())
+    };
+    let iter = |q: &mut TxQueue<_>| {
+        q.iter().collect::<Result<Vec<_>>>()?;
+        Ok(())
+    };
+
+    assert_eq!(interleave(&queue(), push, push), 1);
+    assert_eq!(interleave(&queue(), pop, push), 1);
+    assert_eq!(interleave(&queue(), pop_and_push, push), 1);
+    assert_eq!(interleave(&queue(), peek, push), 1);
+    assert_eq!(interleave(&queue(), push, pop), 1);
+    assert_eq!(interleave(&queue(), pop, pop), 2);
+    assert_eq!(interleave(&queue(), peek, pop), 2);
+    assert_eq!(interleave(&queue(), drain, push), 2);
+    assert_eq!(interleave(&queue(), iter, push), 2);
+    assert_eq!(interleave(&StmQueue::new(), peek, push), 2);
+}
+
+#[test]
+fn concurrent_producers_and_consumer() {
+    let queue = StmQueue::new();
+    let number_of_producers = 8;
+    let items_per_producer = 50;
+    let mut producer_attempts = 0;
+
+    let queue = &queue;
+    let mut consumed = thread::scope(|scope| {
+        let producers: Vec<_> = (0..number_of_producers)
+            .map(|producer| {
+                scope.spawn(move || {
+                    let mut attempts = 0;
+                    for i in 0..items_per_producer {
+                        Tx::run(|tx| -> Result {
+                            attempts += 1;
+                            track!(tx, queue);
+                            queue.push(producer * items_per_producer + i);
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                    attempts
+                })
+            })
+            .collect();
+        let consumer = scope.spawn(|| {
+            let mut consumed = vec![];
+            while consumed.len() < number_of_producers * items_per_producer {
+                let item = Tx::run(|tx| -> Result<_> {
+                    track!(tx, queue);
+                    match queue.pop()? {
+                        Some(item) => Ok(item),
+                        None => {
+                            Tx::retry()?;
+                            unreachable!()
+                        }
+                    }
+                })
+                .unwrap();
+                consumed.push(item);
+            }
+            consumed
+        });
+        for producer in producers {
+            producer_attempts += producer.join().unwrap();
+        }
+        consumer.join().unwrap()
+    });
+
+    // Blind pushes never conflict
+    assert_eq!(producer_attempts, number_of_producers * items_per_producer);
+    consumed.sort();
+    assert_eq!(
+        consumed,
+        (0..number_of_producers * items_per_producer).collect::<Vec<_>>()
+    );
+    assert_eq!(drain_queue(queue), vec![]);
+}
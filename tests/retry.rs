@@ -0,0 +1,82 @@
+use naive_stm::{track, Result, StmCell, StmQueue, Tx};
+use std::{thread, time::Duration};
+
+#[test]
+fn consumer_waits_for_producer() {
+    let queue = StmQueue::new();
+    let mut consumer_attempts = 0;
+
+    let item = thread::scope(|scope| {
+        let consumer = scope.spawn(|| {
+            Tx::run(|tx| -> Result<_> {
+                consumer_attempts += 1;
+                track!(tx, queue);
+                match queue.pop()? {
+                    Some(item) => Ok(item),
+                    None => {
+                        Tx::retry()?;
+                        unreachable!()
+                    }
+                }
+            })
+            .unwrap()
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        Tx::run(|tx| -> Result {
+            track!(tx, queue);
+            queue.push("foo");
+            Ok(())
+        })
+        .unwrap();
+
+        consumer.join().unwrap()
+    });
+
+    assert_eq!(item, "foo");
+    // The consumer must not spin while the queue is empty
+    assert!(consumer_attempts <= 2, "{consumer_attempts}");
+}
+
+#[test]
+fn withdrawals_wait_for_deposits() {
+    let balance = StmCell::new(0);
+    let withdrawals = 10;
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..withdrawals)
+            .map(|_| {
+                scope.spawn(|| {
+                    Tx::run(|tx| -> Result {
+                        track!(tx, balance);
+                        if **balance < 3 {
+                            Tx::retry()?;
+                        }
+                        **balance -= 3;
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        for _ in 0..withdrawals {
+            thread::sleep(Duration::from_millis(1));
+            Tx::run(|tx| -> Result {
+                track!(tx, balance);
+                **balance += 5;
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    });
+
+    assert_eq!(
+        Tx::run(|tx| -> Result<_> { Ok(**tx.track(&balance)?) }).unwrap(),
+        20
+    );
+}
@@ -0,0 +1,259 @@
+use assert_matches::assert_matches;
+use naive_stm::{
+    Backoff, Capped, ConstantPause, DecorrelatedJitter, Error,
+    ExponentialBackoff, Result, RetryContext, RetryPolicy, StmCell, StmVarId,
+    Tx, TxOptions, YieldOnly,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use common::increment;
+
+/// Records the decisions of the wrapped policy
+struct Recording<P> {
+    policy: P,
+    log: Arc<Mutex<Vec<(RetryContext, Backoff)>>>,
+}
+
+impl<P: RetryPolicy> RetryPolicy for Recording<P> {
+    fn backoff(&self, context: &RetryContext) -> Backoff {
+        let backoff = self.policy.backoff(context);
+        self.log.lock().unwrap().push((context.clone(), backoff));
+        backoff
+    }
+}
+
+/// Runs a transaction whose every attempt conflicts on a cell, and returns
+/// the decisions of the retry policy
+fn conflicting_transaction<P>(
+    attempts: usize,
+    policy: P,
+) -> Vec<(RetryContext, Backoff)>
+where
+    P: RetryPolicy + 'static,
+{
+    let cell = StmCell::new(0);
+    let log = Arc::new(Mutex::new(vec![]));
+    let options = TxOptions {
+        attempts,
+        retry_policy: Box::new(Recording {
+            policy,
+            log: Arc::clone(&log),
+        }),
+        ..Default::default()
+    };
+    let result = Tx::run_with_options(&options, |tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: n, .. }) if n == attempts
+    );
+    let log = log.lock().unwrap().clone();
+    assert!(log
+        .iter()
+        .all(|(context, _)| context.conflicting_var == cell.var_id()));
+    log
+}
+
+fn pauses(log: &[(RetryContext, Backoff)]) -> Vec<Duration> {
+    log.iter()
+        .map(|(_, backoff)| match backoff {
+            Backoff::Pause(pause) => *pause,
+            Backoff::Yield => panic!("Unexpected yield"),
+        })
+        .collect()
+}
+
+#[test]
+fn policy_sees_attempts_and_conflicting_var() {
+    let cell_a = StmCell::new(0);
+    let cell_b = StmCell::new(0);
+    let log = Arc::new(Mutex::new(vec![]));
+    let options = TxOptions {
+        attempts: 5,
+        retry_policy: Box::new(Recording {
+            policy: YieldOnly,
+            log: Arc::clone(&log),
+        }),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    Tx::run_with_options(&options, |tx| -> Result {
+        attempts += 1;
+        let a = tx.track(&cell_a)?;
+        match attempts {
+            // Fails at commit
+            1 => increment(&cell_a),
+            // Fails when `cell_b` is tracked
+            2 => increment(&cell_b),
+            _ => (),
+        }
+        **tx.track(&cell_b)? += **a;
+        Ok(())
+    })
+    .unwrap();
+
+    let log = log.lock().unwrap();
+    let contexts: Vec<(usize, StmVarId)> = log
+        .iter()
+        .map(|(context, _)| (context.attempt, context.conflicting_var))
+        .collect();
+    assert_eq!(contexts, vec![(1, cell_a.var_id()), (2, cell_b.var_id())]);
+    assert!(log.iter().all(|(_, backoff)| *backoff == Backoff::Yield));
+}
+
+#[test]
+fn exponential_backoff() {
+    let initial_pause = Duration::from_micros(100);
+    let log = conflicting_transaction(
+        5,
+        ExponentialBackoff {
+            initial_pause,
+            jitter: false,
+        },
+    );
+    assert_eq!(
+        pauses(&log),
+        [1, 2, 4, 8].map(|factor| initial_pause * factor)
+    );
+    let previous_pauses: Vec<_> = log
+        .iter()
+        .map(|(context, _)| context.previous_pause)
+        .collect();
+    assert_eq!(
+        previous_pauses,
+        [0, 1, 2, 4].map(|factor| initial_pause * factor)
+    );
+
+    let log = conflicting_transaction(
+        10,
+        ExponentialBackoff {
+            initial_pause,
+            jitter: true,
+        },
+    );
+    for (i, pause) in pauses(&log).into_iter().enumerate() {
+        assert!(pause < initial_pause * 2_u32.pow(i as u32), "{pause:?}");
+    }
+}
+
+#[test]
+fn capped_backoff() {
+    let log = conflicting_transaction(
+        6,
+        Capped {
+            policy: ExponentialBackoff {
+                initial_pause: Duration::from_micros(100),
+                jitter: false,
+            },
+            max_pause: Duration::from_micros(500),
+        },
+    );
+    assert_eq!(
+        pauses(&log),
+        [100, 200, 400, 500, 500].map(Duration::from_micros)
+    );
+}
+
+#[test]
+fn decorrelated_jitter() {
+    let base_pause = Duration::from_micros(50);
+    let max_pause = Duration::from_micros(1000);
+    let log = conflicting_transaction(
+        20,
+        DecorrelatedJitter {
+            base_pause,
+            max_pause,
+        },
+    );
+    for (context, backoff) in log {
+        let Backoff::Pause(pause) = backoff else {
+            panic!("Unexpected yield")
+        };
+        let upper_bound = (context.previous_pause * 3).max(base_pause);
+        assert!(pause >= base_pause.min(max_pause), "{pause:?}");
+        assert!(pause <= upper_bound.min(max_pause), "{pause:?}");
+    }
+}
+
+/// Records the pauses of the wrapped policy, but only yields
+struct DryRun<P> {
+    policy: P,
+    pauses: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl<P: RetryPolicy> RetryPolicy for DryRun<P> {
+    fn backoff(&self, context: &RetryContext) -> Backoff {
+        if let Backoff::Pause(pause) = self.policy.backoff(context) {
+            self.pauses.lock().unwrap().push(pause)
+        }
+        Backoff::Yield
+    }
+}
+
+#[test]
+fn exponential_backoff_after_many_attempts() {
+    let pauses = Arc::new(Mutex::new(vec![]));
+    conflicting_transaction(
+        100,
+        DryRun {
+            policy: ExponentialBackoff {
+                initial_pause: Duration::from_nanos(1),
+                jitter: false,
+            },
+            pauses: Arc::clone(&pauses),
+        },
+    );
+    let pauses = pauses.lock().unwrap();
+    assert_eq!(pauses.len(), 99);
+    for (exponent, pause) in pauses.iter().enumerate().take(64) {
+        assert_eq!(pause.as_nanos(), 1 << exponent);
+    }
+    // The pause saturates
+    assert_eq!(pauses[98], Duration::MAX);
+}
+
+fn endless_pause() -> TxOptions {
+    TxOptions {
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::MAX,
+            jitter: false,
+        }),
+        deadline: Some(Instant::now() + Duration::from_millis(20)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn endless_pause_ends_with_deadline() {
+    let cell = StmCell::new(0);
+    let result = Tx::run_with_options(&endless_pause(), |tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+}
+
+#[tokio::test]
+async fn endless_async_pause_ends_with_deadline() {
+    let cell = StmCell::new(0);
+    let result = Tx::run_async_with_options(&endless_pause(), |tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    })
+    .await;
+    assert_matches!(result, Err(Error::DeadlineExceeded));
+}
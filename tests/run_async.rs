@@ -0,0 +1,133 @@
+use naive_stm::{
+    track, ConstantPause, Result, StmCell, StmQueue, Tx, TxOptions,
+};
+use std::{cell::RefCell, sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn consumer_waits_for_producer() {
+    let queue = Arc::new(StmQueue::new());
+
+    // Both tasks run on the same thread, so the consumer must not block it
+    let consumer = tokio::spawn({
+        let queue = Arc::clone(&queue);
+        async move {
+            Tx::run_async(|tx| -> Result<_> {
+                let mut queue = tx.track(&*queue)?;
+                match queue.pop()? {
+                    Some(item) => Ok(item),
+                    None => {
+                        Tx::retry()?;
+                        unreachable!()
+                    }
+                }
+            })
+            .await
+            .unwrap()
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    Tx::run_async(|tx| -> Result {
+        tx.track(&*queue)?.push("foo");
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(consumer.await.unwrap(), "foo");
+}
+
+#[tokio::test]
+async fn pause_between_attempts_doesnt_block_executor() {
+    let cell = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 2,
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_millis(100),
+            jitter: false,
+        }),
+        ..Default::default()
+    };
+    let log = RefCell::new(vec![]);
+    let mut attempts = 0;
+
+    let transaction = async {
+        Tx::run_async_with_options(&options, |tx| -> Result {
+            attempts += 1;
+            let mut tx_cell = tx.track(&cell)?;
+            if attempts == 1 {
+                // A concurrent commit makes the first attempt fail
+                Tx::run(|tx| -> Result {
+                    track!(tx, cell);
+                    **cell += 10;
+                    Ok(())
+                })
+                .unwrap();
+            }
+            **tx_cell += 1;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        log.borrow_mut().push("transaction");
+    };
+    let other_task = async {
+        tokio::task::yield_now().await;
+        log.borrow_mut().push("other task");
+    };
+    tokio::join!(transaction, other_task);
+
+    assert_eq!(attempts, 2);
+    assert_eq!(*log.borrow(), vec!["other task", "transaction"]);
+    assert_eq!(
+        Tx::run(|tx| -> Result<_> { Ok(**tx.track(&cell)?) }).unwrap(),
+        11
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_async_transfers() {
+    let account_a = Arc::new(StmCell::new(1000));
+    let account_b = Arc::new(StmCell::new(1000));
+    let options = Arc::new(TxOptions {
+        attempts: 1000,
+        retry_policy: Box::new(ConstantPause {
+            pause: Duration::from_micros(100),
+            jitter: true,
+        }),
+        ..Default::default()
+    });
+
+    let tasks: Vec<_> = (0..16)
+        .map(|i| {
+            let account_a = Arc::clone(&account_a);
+            let account_b = Arc::clone(&account_b);
+            let options = Arc::clone(&options);
+            tokio::spawn(async move {
+                for _ in 0..20 {
+                    Tx::run_async_with_options(&options, |tx| -> Result {
+                        let mut a = tx.track(&*account_a)?;
+                        let mut b = tx.track(&*account_b)?;
+                        let amount = if i % 2 == 0 { 3 } else { -3 };
+                        **a -= amount;
+                        **b += amount;
+                        Ok(())
+                    })
+                    .await
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let balances = Tx::run(|tx| -> Result<_> {
+        let a = tx.track(&*account_a)?;
+        let b = tx.track(&*account_b)?;
+        Ok((**a, **b))
+    })
+    .unwrap();
+    assert_eq!(balances, (1000, 1000));
+}
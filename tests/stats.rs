@@ -0,0 +1,95 @@
+use assert_matches::assert_matches;
+use naive_stm::{ConstantPause, Error, Result, StmCell, Tx, TxOptions};
+use std::time::Duration;
+
+mod common;
+
+use common::increment;
+
+#[test]
+fn stats_of_successful_run() {
+    let cell_a = StmCell::new(0);
+    let cell_b = StmCell::new(0);
+    let pause = Duration::from_millis(10);
+    let options = TxOptions {
+        retry_policy: Box::new(ConstantPause {
+            pause,
+            jitter: false,
+        }),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+
+    let (result, stats) = Tx::run_with_stats(&options, |tx| -> Result<_> {
+        attempts += 1;
+        let a = tx.track(&cell_a)?;
+        match attempts {
+            1 => increment(&cell_a),
+            2 => increment(&cell_b),
+            _ => (),
+        }
+        **tx.track(&cell_b)? += **a;
+        Ok(**a)
+    });
+
+    assert_eq!(result.unwrap(), 1);
+    assert_eq!(stats.attempts, 3);
+    assert_eq!(stats.conflicts, vec![cell_a.var_id(), cell_b.var_id()]);
+    assert_eq!(stats.tracked_vars, 2);
+    assert!(stats.pause_time >= pause * 2, "{:?}", stats.pause_time);
+
+    // A read-only transaction
+    let (result, stats) = Tx::run_with_stats(&options, |tx| -> Result<_> {
+        Ok(**tx.track(&cell_a)?)
+    });
+    assert_eq!(result.unwrap(), 1);
+    assert_eq!(stats.attempts, 1);
+    assert!(stats.conflicts.is_empty());
+    assert_eq!(stats.tracked_vars, 1);
+    assert_eq!(stats.pause_time, Duration::ZERO);
+}
+
+#[test]
+fn stats_of_failed_run() {
+    let cell = StmCell::new(0);
+    let options = TxOptions {
+        attempts: 4,
+        ..Default::default()
+    };
+
+    let (result, stats) = Tx::run_with_stats(&options, |tx| -> Result {
+        let mut tx_cell = tx.track(&cell)?;
+        increment(&cell);
+        **tx_cell += 1;
+        Ok(())
+    });
+
+    assert_matches!(
+        result,
+        Err(Error::TooManyTransactionRetryAttempts { attempts: 4, .. })
+    );
+    assert_eq!(stats.attempts, 4);
+    assert_eq!(stats.conflicts, vec![cell.var_id(); 4]);
+}
+
+#[tokio::test]
+async fn stats_of_async_run() {
+    let cell = StmCell::new(0);
+    let mut attempts = 0;
+
+    let (result, stats) =
+        Tx::run_async_with_stats(&Default::default(), |tx| -> Result {
+            attempts += 1;
+            let mut tx_cell = tx.track(&cell)?;
+            if attempts == 1 {
+                increment(&cell);
+            }
+            **tx_cell += 1;
+            Ok(())
+        })
+        .await;
+
+    result.unwrap();
+    assert_eq!(stats.attempts, 2);
+    assert_eq!(stats.conflicts, vec![cell.var_id()]);
+}
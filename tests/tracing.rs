@@ -0,0 +1,93 @@
+#![cfg(feature = "tracing")]
+
+use naive_stm::{track, Result, StmCell, TracingObserver, Tx};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+
+type Records = Arc<Mutex<Vec<(Level, String)>>>;
+
+/// Records the level and the message of every event
+struct Recorder {
+    records: Records,
+}
+
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}")
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let level = *event.metadata().level();
+        self.records.lock().unwrap().push((level, message.0))
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+#[test]
+fn tracing_observer() {
+    Tx::set_observer(TracingObserver);
+    let cell = StmCell::new(0);
+    let records = Records::default();
+    let subscriber = Recorder {
+        records: Arc::clone(&records),
+    };
+
+    tracing::subscriber::with_default(subscriber, || {
+        Tx::run(|tx| -> Result {
+            track!(tx, cell);
+            **cell += 1;
+            Ok(())
+        })
+        .unwrap();
+        let _ = Tx::run(|tx| {
+            tx.track(&cell)?;
+            Tx::abort()
+        });
+        // Nothing is reported once the observer is removed
+        Tx::remove_observer();
+        Tx::run(|tx| -> Result<_> { Ok(**tx.track(&cell)?) }).unwrap();
+    });
+
+    let records = records.lock().unwrap();
+    assert_eq!(
+        *records,
+        [
+            (Level::TRACE, "STM transaction attempt started"),
+            (Level::TRACE, "STM variable tracked"),
+            (Level::TRACE, "STM transaction committed"),
+            (Level::TRACE, "STM transaction attempt started"),
+            (Level::TRACE, "STM variable tracked"),
+            (Level::DEBUG, "STM transaction aborted"),
+        ]
+        .map(|(level, message)| (level, message.to_owned()))
+    );
+}